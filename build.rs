@@ -0,0 +1,30 @@
+use std::path::Path;
+
+/// The directory block textures are embedded from, as `<texture name>.png`
+const BLOCK_TEXTURE_DIR: &str = "src/res/blocks";
+
+/// Writes the list of block textures to embed into `$OUT_DIR/block_textures.rs`, as a slice of
+/// `(texture name, PNG bytes)` pairs, so that adding a texture only means adding its file
+fn main() {
+    println!("cargo:rerun-if-changed={}", BLOCK_TEXTURE_DIR);
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(BLOCK_TEXTURE_DIR);
+
+    let mut textures: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", dir.display(), e))
+        .map(|entry| entry.expect("failed to read texture directory entry").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "png"))
+        .collect();
+    textures.sort();
+
+    let entries: Vec<String> = textures.iter()
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_str().expect("texture names must be valid UTF-8");
+            format!("    ({:?}, include_bytes!({:?})),\n", name, path)
+        })
+        .collect();
+
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("block_textures.rs");
+    std::fs::write(&out, format!("&[\n{}]\n", entries.concat()))
+        .unwrap_or_else(|e| panic!("failed to write {}: {}", out.display(), e));
+}
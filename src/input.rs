@@ -1,9 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use winit::event::*;
 
 pub struct InputManager {
     key_state: HashMap<VirtualKeyCode, bool>,
+    /// Keys that went down since the last call to [InputManager::end_frame]
+    just_pressed: HashSet<VirtualKeyCode>,
+    /// Mouse buttons that were clicked since the last call to [InputManager::end_frame]
+    clicked: HashSet<MouseButton>,
     mouse_delta: (f64, f64)
 }
 
@@ -11,24 +15,37 @@ impl InputManager {
     pub fn new() -> InputManager {
         InputManager {
             key_state: HashMap::new(),
+            just_pressed: HashSet::new(),
+            clicked: HashSet::new(),
             mouse_delta: (0.0, 0.0)
         }
     }
 
     pub fn process_keyboard(&mut self, event: &WindowEvent) {
-        match event {
-            WindowEvent::KeyboardInput { input: KeyboardInput { 
-                state, virtual_keycode, .. 
-            }, .. } => if let Some(key) = virtual_keycode {
-                self.key_state.insert(key.clone(), state == &ElementState::Pressed);
-            },
-
-            _ => ()
+        if let WindowEvent::KeyboardInput { input: KeyboardInput { 
+            state, virtual_keycode: Some(key), .. 
+        }, .. } = event {
+            let pressed = state == &ElementState::Pressed;
+
+            // ignore key repeats
+            if pressed && !self.is_pressed(*key) {
+                self.just_pressed.insert(*key);
+            }
+
+            self.key_state.insert(*key, pressed);
+        }
+    }
+
+    pub fn process_mouse_button(&mut self, event: &WindowEvent) {
+        if let WindowEvent::MouseInput { state: ElementState::Pressed, button, .. } = event {
+            self.clicked.insert(*button);
         }
     }
 
+    /// Adds a mouse movement to the total for this frame, since several can arrive between frames
     pub fn process_mouse(&mut self, (dx, dy): (f64, f64)) {
-        self.mouse_delta = (dx, -dy);
+        self.mouse_delta.0 += dx;
+        self.mouse_delta.1 -= dy;
     }
 
     pub fn is_pressed(&self, key: VirtualKeyCode) -> bool {
@@ -36,6 +53,23 @@ impl InputManager {
             .cloned().unwrap_or(false)
     }
 
+    /// Whether a key was pressed down during this frame
+    pub fn was_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.just_pressed.contains(&key)
+    }
+
+    /// Whether a mouse button was clicked during this frame
+    pub fn was_clicked(&self, button: MouseButton) -> bool {
+        self.clicked.contains(&button)
+    }
+
+    /// Resets the per-frame input state; called once the frame has been rendered
+    pub fn end_frame(&mut self) {
+        self.mouse_delta = (0.0, 0.0);
+        self.just_pressed.clear();
+        self.clicked.clear();
+    }
+
     pub fn delta(&self) -> (f64, f64) {
         self.mouse_delta
     }
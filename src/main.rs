@@ -1,25 +1,126 @@
 mod renderer;
 mod input;
+mod player;
+mod timestep;
+mod screenshot;
 mod world;
 
 use winit::{
-    window::{WindowBuilder, CursorGrabMode},
+    window::{WindowBuilder, CursorGrabMode, Fullscreen},
     event_loop::{EventLoop, ControlFlow},
-    event::{Event, WindowEvent, DeviceEvent, KeyboardInput, VirtualKeyCode, ElementState},
-    dpi::LogicalSize
+    event::{Event, WindowEvent, DeviceEvent, KeyboardInput, VirtualKeyCode, ElementState, MouseButton},
+    dpi::{LogicalSize, PhysicalSize}
 };
 
+use glam::{Vec3, vec3};
+
+use world::{World, Aabb, BlockId, BlockRegistry, MeshingMode, NoiseTerrain, ChunkManager, RegionStore, blocks};
+use renderer::{Camera, Renderer};
+use player::{Player, MovementMode};
+use timestep::FixedTimestep;
+
+/// The seed used to generate the world
+const WORLD_SEED: u64 = 0x5eed;
+
+/// How far away, in blocks, the player can reach
+const REACH: f32 = 8.0;
+
+/// How far the camera turns for each unit of mouse movement, in degrees
+const MOUSE_SENSITIVITY: f32 = 0.04;
+
+/// The directory edited chunks are saved in
+const WORLD_DIR: &str = "world";
+
+/// The directory screenshots are saved in
+const SCREENSHOT_DIR: &str = "screenshots";
+
+/// The blocks that can be placed, selected with the number keys
+const HOTBAR: [BlockId; 6] = [blocks::STONE, blocks::DIRT, blocks::GRASS, blocks::SAND, blocks::GLASS, blocks::LAMP];
+const HOTBAR_KEYS: [VirtualKeyCode; 6] = [
+    VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4, VirtualKeyCode::Key5, VirtualKeyCode::Key6
+];
+
+/// Breaks the block the camera is looking at on left click, and places the selected block against it on right click.
+/// Solid blocks aren't placed where they would overlap the player.
+fn edit_world(world: &mut World, input: &input::InputManager, camera: &Camera, player: &Player, selected: BlockId) {
+    let breaking = input.was_clicked(MouseButton::Left);
+    let placing = input.was_clicked(MouseButton::Right);
+
+    if !breaking && !placing {
+        return;
+    }
+
+    let Some(hit) = world.raycast(camera.position, camera.front(), REACH) else {
+        return;
+    };
+
+    if breaking {
+        world.set_block(hit.block, BlockId::AIR);
+    } else if !world.registry().get(world.get_block(hit.previous)).solid {
+        let blocks_player = world.registry().get(selected).solid && Aabb::block(hit.previous).intersects(&player.aabb());
+
+        if !blocks_player {
+            world.set_block(hit.previous, selected);
+        }
+    }
+}
+
+/// Creates the world, along with the chunk manager that fills it in, and finds the point the player spawns at
+fn create_world() -> (World, ChunkManager, Vec3) {
+    let terrain = NoiseTerrain::new(WORLD_SEED);
+    let spawn = vec3(0.0, terrain.height_at(0, 0) as f32 + 2.0, 0.0);
+
+    let world = World::new(BlockRegistry::with_default_blocks());
+    let chunks = ChunkManager::new(Box::new(terrain), RegionStore::new(WORLD_DIR), 6, 8);
+
+    (world, chunks, spawn)
+}
+
+/// Renders the view from the spawn point into a PNG at `path`, without opening a window
+fn render_headless(path: &str) -> anyhow::Result<()> {
+    let (mut world, mut chunks, spawn) = create_world();
+
+    let mut renderer = Renderer::headless(PhysicalSize::new(1280, 720), world.registry())?;
+    renderer.camera.position = spawn;
+
+    // everything in view has to be loaded and meshed before the one and only frame
+    chunks.update(&mut world, spawn);
+    while chunks.is_loading() {
+        chunks.update(&mut world, spawn);
+    }
+
+    for pos in world.take_dirty() {
+        renderer.update_chunk(&world, pos);
+    }
+    renderer.finish_meshing();
+
+    renderer.render()?;
+    renderer.read_pixels()?.save(path)?;
+
+    let stats = renderer.stats();
+    log::info!("saved headless render to {} (drew {} chunks, culled {})", path, stats.visible, stats.culled);
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     env_logger::builder()
         // .filter_level(log::LevelFilter::Info)
         .init();
 
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag, path] = args.as_slice() {
+        if flag == "--headless" {
+            return render_headless(path);
+        }
+    }
+
     log::info!("creating window");
     
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("Voxel Engine")
-        .with_resizable(false)
+        .with_resizable(true)
         .with_inner_size(LogicalSize::new(1280, 720))
         .build(&event_loop)
         .unwrap();
@@ -28,13 +129,23 @@ fn main() -> anyhow::Result<()> {
     window.set_cursor_grab(CursorGrabMode::Locked)
         .expect("failed to grab cursor");
 
-    let mut renderer = renderer::Renderer::init(window)?;
+    let (mut world, mut chunks, spawn) = create_world();
+
+    let window_id = window.id();
+    let mut renderer = Renderer::init(window, world.registry())?;
+    renderer.camera.position = spawn;
+
+    let mut player = Player::new(spawn);
+    let mut timestep = FixedTimestep::from_env();
+    let mut last_frame = std::time::Instant::now();
+
     let mut input = input::InputManager::new();
+    let mut selected = HOTBAR[0];
 
     log::info!("starting event loop");
     event_loop.run(move |event, _, control_flow| {
         match event {
-            Event::WindowEvent { window_id, ref event } if renderer.window().id() == window_id => {
+            Event::WindowEvent { window_id: id, ref event } if id == window_id => {
                 match event {
                     // handles user press escape or pressing the close button
                     WindowEvent::CloseRequested |
@@ -43,7 +154,11 @@ fn main() -> anyhow::Result<()> {
                     }, .. } => *control_flow = ControlFlow::Exit,
 
 
+                    WindowEvent::Resized(size) => renderer.resize(*size),
+                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => renderer.resize(**new_inner_size),
+
                     event @ WindowEvent::KeyboardInput { .. } => input.process_keyboard(event),
+                    event @ WindowEvent::MouseInput { .. } => input.process_mouse_button(event),
 
                     _ => ()
                 }
@@ -51,29 +166,125 @@ fn main() -> anyhow::Result<()> {
 
             Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => input.process_mouse(delta),
 
-            Event::RedrawRequested(window_id) => if renderer.window().id() == window_id {
+            Event::RedrawRequested(id) if id == window_id => {
+                if input.was_pressed(VirtualKeyCode::G) {
+                    let meshing = match world.meshing() {
+                        MeshingMode::Naive => MeshingMode::Greedy,
+                        MeshingMode::Greedy => MeshingMode::Naive
+                    };
+
+                    log::info!("switching to {:?} meshing", meshing);
+                    world.set_meshing(meshing);
+                }
+
+                if let Some(window) = renderer.window().filter(|_| input.was_pressed(VirtualKeyCode::F11)) {
+                    // the resulting resize arrives as a normal Resized event
+                    match window.fullscreen() {
+                        Some(_) => window.set_fullscreen(None),
+                        None => window.set_fullscreen(Some(Fullscreen::Borderless(None)))
+                    }
+                }
+
+                if input.was_pressed(VirtualKeyCode::F) {
+                    player.toggle_mode();
+                    log::info!("switching to {:?}", player.mode);
+                }
+
+                if input.was_pressed(VirtualKeyCode::F2) {
+                    renderer.request_screenshot();
+                }
+
+                if input.was_pressed(VirtualKeyCode::F3) {
+                    log::info!("world memory: {}", world.memory_stats());
+
+                    let stats = renderer.stats();
+                    log::info!("drew {} chunks, culled {}", stats.visible, stats.culled);
+
+                    match world.raycast(renderer.camera.position, renderer.camera.front(), REACH) {
+                        Some(hit) => log::info!("looking at {:?} at {}", world.get_block(hit.block), hit.block),
+                        None => log::info!("not looking at a block")
+                    }
+                }
+
+                if input.was_pressed(VirtualKeyCode::F4) {
+                    renderer.toggle_wireframe();
+                }
+
+                for (key, &block) in HOTBAR_KEYS.iter().zip(HOTBAR.iter()) {
+                    if input.was_pressed(*key) {
+                        selected = block;
+                        log::info!("selected {}", world.registry().get(block).name);
+                    }
+                }
+
+                edit_world(&mut world, &input, &renderer.camera, &player, selected);
+
+                renderer.outline.target = world.raycast(renderer.camera.position, renderer.camera.front(), REACH)
+                    .map(|hit| hit.block);
+
+                chunks.update(&mut world, renderer.camera.position);
+
+                for pos in world.take_dirty() {
+                    renderer.update_chunk(&world, pos);
+                }
+                renderer.upload_meshes();
+
+                // looking around happens every frame rather than every tick, so it stays responsive
                 let (dx, dy) = input.delta();
-                renderer.camera.rotate(dx as f32 * 0.04, dy as f32 * 0.04);
-
-                renderer.camera.travel(
-                    input.is_pressed(VirtualKeyCode::W),
-                    input.is_pressed(VirtualKeyCode::S),
-                    input.is_pressed(VirtualKeyCode::A),
-                    input.is_pressed(VirtualKeyCode::D),
-                    input.is_pressed(VirtualKeyCode::Space),
-                    input.is_pressed(VirtualKeyCode::C),
-                );
-
-                match renderer.render() {
-                    Err(e) => log::error!("rendering failed: {}", e),
-                    Ok(_) => ()
+                renderer.camera.rotate(dx as f32 * MOUSE_SENSITIVITY, dy as f32 * MOUSE_SENSITIVITY);
+
+                let now = std::time::Instant::now();
+                let ticks = timestep.advance(now - last_frame);
+                last_frame = now;
+
+                for _ in 0..ticks {
+                    let forward = input.is_pressed(VirtualKeyCode::W);
+                    let backward = input.is_pressed(VirtualKeyCode::S);
+                    let left = input.is_pressed(VirtualKeyCode::A);
+                    let right = input.is_pressed(VirtualKeyCode::D);
+
+                    match player.mode {
+                        MovementMode::Walking => {
+                            let direction = renderer.camera.walk_direction(forward, backward, left, right);
+                            let jump = input.is_pressed(VirtualKeyCode::Space);
+
+                            player.walk(direction, jump, timestep.dt(), |pos| world.is_solid(pos));
+                        },
+                        MovementMode::Flying => {
+                            let direction = renderer.camera.fly_direction(
+                                forward, backward, left, right,
+                                input.is_pressed(VirtualKeyCode::Space),
+                                input.is_pressed(VirtualKeyCode::C)
+                            );
+
+                            player.fly(direction, timestep.dt());
+                        }
+                    }
+                }
+
+                renderer.camera.position = player.interpolated_eye_position(timestep.alpha());
+
+                if let Err(e) = renderer.render() {
+                    log::error!("rendering failed: {}", e);
                 }
 
-                input.process_mouse((0.0, 0.0));
+                for result in renderer.take_screenshots() {
+                    match result {
+                        Ok(image) => screenshot::save(image, std::path::Path::new(SCREENSHOT_DIR)),
+                        Err(e) => log::error!("failed to read back screenshot: {}", e)
+                    }
+                }
+
+                input.end_frame();
             },
 
+            // keep any edits made to the chunks that are still loaded
+            Event::LoopDestroyed => chunks.save_all(&mut world),
+
             Event::MainEventsCleared => {
-                renderer.window().request_redraw()
+                if let Some(window) = renderer.window() {
+                    window.request_redraw();
+                }
             },
 
             _ => ()
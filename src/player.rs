@@ -0,0 +1,138 @@
+use glam::*;
+
+use crate::world::{Aabb, move_and_collide};
+
+/// The size of the player's bounding box
+const WIDTH: f32 = 0.6;
+const HEIGHT: f32 = 1.8;
+/// The height of the player's eyes above their feet
+const EYE_HEIGHT: f32 = 1.62;
+
+/// Acceleration due to gravity, in blocks per second squared
+const GRAVITY: f32 = 28.0;
+/// The upwards speed given by a jump, in blocks per second
+const JUMP_SPEED: f32 = 8.5;
+/// The fastest the player can fall, in blocks per second
+const TERMINAL_VELOCITY: f32 = 60.0;
+/// How fast the player walks, in blocks per second
+const WALK_SPEED: f32 = 4.3;
+/// How fast the player flies, in blocks per second
+const FLY_SPEED: f32 = 12.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MovementMode {
+    /// Affected by gravity, and collides with the world
+    Walking,
+    /// Free movement through the world, without collision
+    Flying
+}
+
+pub struct Player {
+    /// The point at the bottom centre of the player's bounding box
+    pub position: Vec3,
+    /// The player's position before the last update, for interpolating between updates
+    previous_position: Vec3,
+    pub velocity: Vec3,
+    /// Whether the player was standing on something at the end of the last update
+    pub on_ground: bool,
+    pub mode: MovementMode
+}
+
+impl Player {
+    pub fn new(position: Vec3) -> Player {
+        Player { position, previous_position: position, velocity: Vec3::ZERO, on_ground: false, mode: MovementMode::Walking }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(
+            self.position - vec3(WIDTH / 2.0, 0.0, WIDTH / 2.0),
+            self.position + vec3(WIDTH / 2.0, HEIGHT, WIDTH / 2.0)
+        )
+    }
+
+    /// The eye position a fraction `alpha` of the way from the previous update to the latest one
+    pub fn interpolated_eye_position(&self, alpha: f32) -> Vec3 {
+        self.previous_position.lerp(self.position, alpha) + vec3(0.0, EYE_HEIGHT, 0.0)
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            MovementMode::Walking => MovementMode::Flying,
+            MovementMode::Flying => MovementMode::Walking
+        };
+
+        self.velocity = Vec3::ZERO;
+    }
+
+    /// Advances a walking player by `dt` seconds. `direction` is the horizontal direction the player wants to walk in,
+    /// and `is_solid` says which blocks the player collides with.
+    pub fn walk(&mut self, direction: Vec3, jump: bool, dt: f32, is_solid: impl Fn(IVec3) -> bool) {
+        self.previous_position = self.position;
+
+        let direction = vec3(direction.x, 0.0, direction.z).normalize_or_zero();
+
+        self.velocity.x = direction.x * WALK_SPEED;
+        self.velocity.z = direction.z * WALK_SPEED;
+        self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+
+        if jump && self.on_ground {
+            self.velocity.y = JUMP_SPEED;
+        }
+
+        let (aabb, blocked) = move_and_collide(self.aabb(), self.velocity * dt, is_solid);
+
+        self.position = vec3((aabb.min.x + aabb.max.x) / 2.0, aabb.min.y, (aabb.min.z + aabb.max.z) / 2.0);
+        self.on_ground = blocked[1] && self.velocity.y <= 0.0;
+
+        for (axis, &blocked) in blocked.iter().enumerate() {
+            if blocked {
+                self.velocity[axis] = 0.0;
+            }
+        }
+    }
+
+    /// Advances a flying player by `dt` seconds, moving them in `direction` without colliding with anything
+    pub fn fly(&mut self, direction: Vec3, dt: f32) {
+        self.previous_position = self.position;
+        self.position += direction.normalize_or_zero() * FLY_SPEED * dt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Solid ground for every block below y = 0
+    fn ground(pos: IVec3) -> bool {
+        pos.y < 0
+    }
+
+    #[test]
+    fn falls_and_lands() {
+        let mut player = Player::new(vec3(0.0, 5.0, 0.0));
+
+        for _ in 0..120 {
+            player.walk(Vec3::ZERO, false, 1.0 / 60.0, ground);
+        }
+
+        assert!(player.on_ground);
+        assert!((player.position.y + 0.5).abs() < 1e-3);
+        assert_eq!(player.velocity, Vec3::ZERO);
+    }
+
+    #[test]
+    fn jumps_only_from_ground() {
+        let mut player = Player::new(vec3(0.0, 5.0, 0.0));
+
+        player.walk(Vec3::ZERO, true, 1.0 / 60.0, ground);
+        assert!(player.velocity.y < 0.0);
+
+        for _ in 0..120 {
+            player.walk(Vec3::ZERO, false, 1.0 / 60.0, ground);
+        }
+
+        player.walk(Vec3::ZERO, true, 1.0 / 60.0, ground);
+        assert!(player.velocity.y > 0.0);
+        assert!(player.position.y > -0.5);
+    }
+}
@@ -1,6 +1,6 @@
 use glam::*;
 
-use super::GpuResource;
+use super::{GpuResource, Frustum};
 
 pub struct Camera {
     buffer: wgpu::Buffer,
@@ -18,6 +18,7 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(device: &wgpu::Device, position: Vec3, pitch: f32, yaw: f32, fovy: f32, aspect: f32, znear: f32, zfar: f32) -> Camera {
         let buffer = device.create_buffer(
             &wgpu::BufferDescriptor {
@@ -67,29 +68,45 @@ impl Camera {
         self.pitch = (self.pitch + pitch).clamp(-89.0, 89.0);
     }
 
-    pub fn travel(&mut self, forward: bool, backward: bool, left: bool, right: bool, up: bool, down: bool) {
+    /// The horizontal direction of movement for the held movement keys, relative to where the camera is facing. Zero if
+    /// no keys are held or they cancel out.
+    pub fn walk_direction(&self, forward: bool, backward: bool, left: bool, right: bool) -> Vec3 {
+        let front = self.front_anchored().normalize_or_zero();
+        let right_dir = self.right();
+
+        let mut direction = Vec3::ZERO;
         if forward {
-            self.position += self.front_anchored() * 0.01;
+            direction += front;
         }
         if backward {
-            self.position -= self.front_anchored() * 0.01;
+            direction -= front;
         }
         if left {
-            self.position -= self.right() * 0.01;
+            direction -= right_dir;
         }
         if right {
-            self.position += self.right() * 0.01;
+            direction += right_dir;
         }
 
+        direction.normalize_or_zero()
+    }
+
+    /// The direction of movement for the held movement keys when flying, which moves vertically with `up` and `down`
+    /// rather than by looking up or down
+    pub fn fly_direction(&self, forward: bool, backward: bool, left: bool, right: bool, up: bool, down: bool) -> Vec3 {
+        let mut direction = self.walk_direction(forward, backward, left, right);
         if up {
-            self.position.y += 0.01;
+            direction.y += 1.0;
         }
         if down {
-            self.position.y -= 0.01;
+            direction.y -= 1.0;
         }
+
+        direction.normalize_or_zero()
     }
 
-    fn front(&self) -> Vec3 {
+    /// The direction the camera is looking in
+    pub fn front(&self) -> Vec3 {
         vec3(
             self.yaw.to_radians().cos() * self.pitch.to_radians().cos(),
             self.pitch.to_radians().sin(),
@@ -97,6 +114,19 @@ impl Camera {
         ).normalize()
     }
 
+    /// The matrix taking points from world space to clip space
+    pub fn view_projection(&self) -> Mat4 {
+        let view = Mat4::look_at_rh(self.position, self.position + self.front(), self.up());
+        let proj = Mat4::perspective_rh(self.fovy.to_radians(), self.aspect, self.znear, self.zfar);
+
+        proj * view
+    }
+
+    /// The region of the world the camera can see
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(self.view_projection())
+    }
+
     fn front_anchored(&self) -> Vec3 {
         let mut front = self.front();
         front.y = 0.0;
@@ -123,10 +153,7 @@ impl GpuResource for Camera {
     }
 
     fn update(&self, queue: &wgpu::Queue) {
-        let view = glam::Mat4::look_at_rh(self.position, self.position + self.front(), self.up());
-        let proj = glam::Mat4::perspective_rh(self.fovy.to_radians(), self.aspect, self.znear, self.zfar);
-        
-        let matrix = proj * view;
+        let matrix = self.view_projection();
 
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[matrix]));
     }
@@ -0,0 +1,78 @@
+use glam::{Mat4, Vec4, Vec3};
+
+use crate::world::Aabb;
+
+/// The region of space a camera can see, bounded by six planes
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    /// Each plane as (normal, distance), with the normal pointing into the frustum; a point `p` is on the inside of a
+    /// plane when `normal.dot(p) + distance >= 0`
+    planes: [Vec4; 6]
+}
+
+impl Frustum {
+    /// Extracts the frustum from a view-projection matrix, which maps it onto wgpu's clip space: -1 to 1 in X and Y,
+    /// and 0 to 1 in Z
+    pub fn from_matrix(view_projection: Mat4) -> Frustum {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_projection.row(i));
+
+        let planes = [w + x, w - x, w + y, w - y, z, w - z]
+            .map(|plane| plane / plane.truncate().length());
+
+        Frustum { planes }
+    }
+
+    /// Whether any of `aabb` might be inside the frustum. Boxes near the corners of the frustum can be counted as
+    /// inside when they aren't, but a box that is inside is never counted as outside.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+
+            // the corner of the box furthest along the plane's normal; if that is outside, the whole box is
+            let corner = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+
+    /// A frustum at the origin looking down -Z, with a 90 degree field of view and a far plane at 100
+    fn frustum() -> Frustum {
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        let projection = Mat4::perspective_rh(90f32.to_radians(), 1.0, 0.1, 100.0);
+
+        Frustum::from_matrix(projection * view)
+    }
+
+    fn cube(centre: Vec3) -> Aabb {
+        Aabb::new(centre - 0.5, centre + 0.5)
+    }
+
+    #[test]
+    fn boxes_in_view() {
+        let frustum = frustum();
+
+        assert!(frustum.intersects(&cube(vec3(0.0, 0.0, -10.0))));
+        assert!(frustum.intersects(&cube(vec3(9.0, -9.0, -10.0))));
+        // around the camera itself
+        assert!(frustum.intersects(&cube(Vec3::ZERO)));
+        // only partly in view, off the right edge and past the far plane
+        assert!(frustum.intersects(&cube(vec3(10.4, 0.0, -10.0))));
+        assert!(frustum.intersects(&cube(vec3(0.0, 0.0, -100.4))));
+    }
+
+    #[test]
+    fn boxes_out_of_view() {
+        let frustum = frustum();
+
+        assert!(!frustum.intersects(&cube(vec3(0.0, 0.0, 10.0))));
+        assert!(!frustum.intersects(&cube(vec3(12.0, 0.0, -10.0))));
+        assert!(!frustum.intersects(&cube(vec3(0.0, -12.0, -10.0))));
+        assert!(!frustum.intersects(&cube(vec3(0.0, 0.0, -101.0))));
+    }
+}
@@ -1,17 +1,47 @@
+use std::ops::Range;
+
 use wgpu::util::DeviceExt;
 
 use glam::*;
 
-use super::Vertex;
+use super::{Vertex, Error};
+use crate::world::{FaceTextures, Light, MAX_LIGHT, Aabb};
+
+/// The ambient occlusion level of a vertex with nothing around it
+pub const MAX_OCCLUSION: u8 = 3;
+
+/// How brightly each corner of a face is lit, in the order used by [MeshBuilder::add_face]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FaceShading {
+    /// Ambient occlusion levels, from 0 (darkest) to [MAX_OCCLUSION] (unoccluded)
+    pub occlusion: [u8; 4],
+    pub light: [Light; 4]
+}
 
 pub struct Mesh {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
-    pub index_count: u32
+    /// The indices of the faces that hide what is behind them, drawn first
+    pub opaque_indices: Range<u32>,
+    /// The indices of the faces that can be seen through, which follow the opaque ones in the index buffer
+    pub transparent_indices: Range<u32>,
+    pub index_format: wgpu::IndexFormat,
+    /// The world-space box around every vertex, or `None` if the mesh is empty
+    pub bounds: Option<Aabb>
 }
 
 impl Mesh {
-    pub fn new(device: &wgpu::Device, vertices: &[Vertex], indices: &[u16]) -> Mesh {
+    /// Uploads a mesh to the GPU. Indices are stored as 16-bit when there are few enough vertices, and 32-bit otherwise.
+    pub fn new(device: &wgpu::Device, data: &MeshData) -> Mesh {
+        let MeshData { vertices, indices, transparent_indices } = data;
+        let all_indices = indices.iter().chain(transparent_indices);
+
+        let index_format = index_format(vertices.len());
+        let contents = match index_format {
+            wgpu::IndexFormat::Uint16 => bytemuck::cast_slice(&all_indices.map(|&i| i as u16).collect::<Vec<_>>()).to_vec(),
+            wgpu::IndexFormat::Uint32 => bytemuck::cast_slice(&all_indices.copied().collect::<Vec<_>>()).to_vec()
+        };
+
         let vertex_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Vertex Buffer"),
@@ -23,18 +53,104 @@ impl Mesh {
         let index_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(indices),
+                contents: &contents,
                 usage: wgpu::BufferUsages::INDEX
             }
         );
 
         Mesh {
             vertex_buffer, index_buffer,
-            index_count: indices.len() as u32
+            opaque_indices: 0..indices.len() as u32,
+            transparent_indices: indices.len() as u32..(indices.len() + transparent_indices.len()) as u32,
+            index_format,
+            bounds: Aabb::enclosing(vertices.iter().map(|vertex| vertex.position))
         }
     }
 }
 
+/// One of the six faces of a cube. North/south lie along the Z axis, east/west along the X axis.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Face {
+    Up, Down, North, South, East, West
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [Face::Up, Face::Down, Face::North, Face::South, Face::East, Face::West];
+
+    /// The unit vector pointing out of this face
+    pub fn normal(self) -> IVec3 {
+        match self {
+            Face::Up => ivec3(0, 1, 0),
+            Face::Down => ivec3(0, -1, 0),
+            Face::North => ivec3(0, 0, 1),
+            Face::South => ivec3(0, 0, -1),
+            Face::East => ivec3(1, 0, 0),
+            Face::West => ivec3(-1, 0, 0)
+        }
+    }
+
+    /// The bit representing this face in a [CubeFaces] set
+    fn bit(self) -> u8 {
+        match self {
+            Face::Up => CubeFaces::UP,
+            Face::Down => CubeFaces::DOWN,
+            Face::North => CubeFaces::NORTH,
+            Face::South => CubeFaces::SOUTH,
+            Face::East => CubeFaces::EAST,
+            Face::West => CubeFaces::WEST
+        }
+    }
+
+    /// The two (positive) grid axes that this face lies across
+    pub fn tangents(self) -> (IVec3, IVec3) {
+        match self {
+            Face::Up | Face::Down => (IVec3::X, IVec3::Z),
+            Face::North | Face::South => (IVec3::X, IVec3::Y),
+            Face::East | Face::West => (IVec3::Z, IVec3::Y)
+        }
+    }
+
+    /// The directions that a texture's U and V coordinates increase in across this face, as seen from outside the
+    /// cube; for the side faces V points downwards, so that textures are drawn upright.
+    fn texture_axes(self) -> (Vec3, Vec3) {
+        match self {
+            Face::Up => (Vec3::X, Vec3::Z),
+            Face::Down => (Vec3::X, Vec3::NEG_Z),
+            Face::North => (Vec3::X, Vec3::NEG_Y),
+            Face::South => (Vec3::NEG_X, Vec3::NEG_Y),
+            Face::East => (Vec3::NEG_Z, Vec3::NEG_Y),
+            Face::West => (Vec3::Z, Vec3::NEG_Y)
+        }
+    }
+}
+
+/// The vertices and indices of a mesh that has been built on the CPU, but not yet uploaded to the GPU
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    /// The triangles of opaque faces
+    pub indices: Vec<u32>,
+    /// The triangles of faces that are blended with what is behind them
+    pub transparent_indices: Vec<u32>
+}
+
+/// The smallest index format that can address `vertex_count` vertices
+fn index_format(vertex_count: usize) -> wgpu::IndexFormat {
+    if vertex_count <= u16::MAX as usize + 1 {
+        wgpu::IndexFormat::Uint16
+    } else {
+        wgpu::IndexFormat::Uint32
+    }
+}
+
+/// The index of the first vertex of a quad added to a mesh that already has `vertex_count` vertices. Fails if any of
+/// the quad's four vertices couldn't be addressed by a 32-bit index.
+fn first_index(vertex_count: usize) -> Result<u32, Error> {
+    u32::try_from(vertex_count)
+        .ok()
+        .filter(|start| start.checked_add(3).is_some())
+        .ok_or(Error::MeshTooLarge(vertex_count))
+}
+
 pub struct CubeFaces(u8);
 
 impl CubeFaces {
@@ -49,110 +165,130 @@ impl CubeFaces {
         let (up, down, north, south, east, west) = (up as u8, down as u8, north as u8, south as u8, east as u8, west as u8);
 
         CubeFaces(
-            up * CubeFaces::UP |
-            down * CubeFaces::DOWN |
-            north * CubeFaces::NORTH |
-            south * CubeFaces::SOUTH |
-            east * CubeFaces::EAST |
-            west * CubeFaces::WEST
+            (up * CubeFaces::UP) |
+            (down * CubeFaces::DOWN) |
+            (north * CubeFaces::NORTH) |
+            (south * CubeFaces::SOUTH) |
+            (east * CubeFaces::EAST) |
+            (west * CubeFaces::WEST)
         )
     }
+
+    pub fn contains(&self, face: Face) -> bool {
+        self.0 & face.bit() > 0
+    }
 }
 
 pub struct MeshBuilder {
     vertices: Vec<Vertex>,
-    indices: Vec<u16>
+    indices: Vec<u32>,
+    transparent_indices: Vec<u32>
 }
 
 impl MeshBuilder {
     pub fn new() -> MeshBuilder {
-        MeshBuilder { vertices: vec![], indices: vec![] }
+        MeshBuilder { vertices: vec![], indices: vec![], transparent_indices: vec![] }
     }
 
-    pub fn add_cube(&mut self, pos: glam::Vec3, faces: CubeFaces) {
-        if faces.0 & CubeFaces::UP > 0 {
-            let start = self.vertices.len() as u16;
-            self.vertices.extend_from_slice(&[
-                Vertex { position: pos + vec3(0.5, 0.5, 0.5), uv: vec2(1.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, 0.5, 0.5), uv: vec2(0.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, 0.5, -0.5), uv: vec2(0.0, 0.0)},
-                Vertex { position: pos + vec3(0.5, 0.5, -0.5), uv: vec2(1.0, 0.0)},
-            ]);
-            self.indices.extend_from_slice(&[
-                start + 2, start + 1, start + 0,
-                start + 3, start + 2, start + 0
-            ]);
-        }
-        if faces.0 & CubeFaces::DOWN > 0 {
-            let start = self.vertices.len() as u16;
-            self.vertices.extend_from_slice(&[
-                Vertex { position: pos + vec3(0.5, -0.5, 0.5), uv: vec2(1.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, -0.5, 0.5), uv: vec2(0.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, -0.5, -0.5), uv: vec2(0.0, 0.0)},
-                Vertex { position: pos + vec3(0.5, -0.5, -0.5), uv: vec2(1.0, 0.0)},
-            ]);
-            self.indices.extend_from_slice(&[
-                start + 0, start + 1, start + 2,
-                start + 0, start + 2, start + 3
-            ]);
+    /// Adds the visible faces of a single block, with each face shaded by `shading`. `transparent` is whether the
+    /// block can be seen through, as in [MeshBuilder::add_face].
+    pub fn add_cube(
+        &mut self, pos: glam::Vec3, faces: CubeFaces, textures: &FaceTextures, transparent: bool,
+        shading: impl Fn(Face) -> FaceShading
+    ) -> Result<(), Error> {
+        for face in Face::ALL {
+            if faces.contains(face) {
+                self.add_face(pos, face, UVec2::ONE, textures.get(face).0, transparent, shading(face))?;
+            }
         }
 
-        if faces.0 & CubeFaces::NORTH > 0 {
-            let start = self.vertices.len() as u16;
-            self.vertices.extend_from_slice(&[
-                Vertex { position: pos + vec3(0.5, 0.5, 0.5), uv: vec2(1.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, 0.5, 0.5), uv: vec2(0.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, -0.5, 0.5), uv: vec2(0.0, 0.0)},
-                Vertex { position: pos + vec3(0.5, -0.5, 0.5), uv: vec2(1.0, 0.0)},
-            ]);
-            self.indices.extend_from_slice(&[
-                start + 0, start + 1, start + 2,
-                start + 0, start + 2, start + 3
-            ]);
-        }
-        if faces.0 & CubeFaces::SOUTH > 0 {
-            let start = self.vertices.len() as u16;
-            self.vertices.extend_from_slice(&[
-                Vertex { position: pos + vec3(0.5, 0.5, -0.5), uv: vec2(1.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, 0.5, -0.5), uv: vec2(0.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, -0.5, -0.5), uv: vec2(0.0, 0.0)},
-                Vertex { position: pos + vec3(0.5, -0.5, -0.5), uv: vec2(1.0, 0.0)},
-            ]);
-            self.indices.extend_from_slice(&[
-                start + 2, start + 1, start + 0,
-                start + 3, start + 2, start + 0
-            ]);
-        }
+        Ok(())
+    }
 
-        if faces.0 & CubeFaces::EAST > 0 {
-            let start = self.vertices.len() as u16;
-            self.vertices.extend_from_slice(&[
-                Vertex { position: pos + vec3(0.5, 0.5, 0.5), uv: vec2(1.0, 1.0)},
-                Vertex { position: pos + vec3(0.5, 0.5, -0.5), uv: vec2(0.0, 1.0)},
-                Vertex { position: pos + vec3(0.5, -0.5, -0.5), uv: vec2(0.0, 0.0)},
-                Vertex { position: pos + vec3(0.5, -0.5, 0.5), uv: vec2(1.0, 0.0)},
-            ]);
-            self.indices.extend_from_slice(&[
-                start + 2, start + 1, start + 0,
-                start + 3, start + 2, start + 0
-            ]);
-        }
-        if faces.0 & CubeFaces::WEST > 0 {
-            let start = self.vertices.len() as u16;
-            self.vertices.extend_from_slice(&[
-                Vertex { position: pos + vec3(-0.5, 0.5, 0.5), uv: vec2(1.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, 0.5, -0.5), uv: vec2(0.0, 1.0)},
-                Vertex { position: pos + vec3(-0.5, -0.5, -0.5), uv: vec2(0.0, 0.0)},
-                Vertex { position: pos + vec3(-0.5, -0.5, 0.5), uv: vec2(1.0, 0.0)},
-            ]);
-            self.indices.extend_from_slice(&[
-                start + 0, start + 1, start + 2,
-                start + 0, start + 2, start + 3
-            ]);
+    /// Adds a rectangle of faces pointing in the direction of `face`. The rectangle starts at the block centred on
+    /// `pos`, and covers `size` blocks along each of the face's [tangents](Face::tangents). The texture is repeated
+    /// once per block.
+    ///
+    /// Transparent faces are kept apart from the rest, so that they can be drawn after everything opaque.
+    ///
+    /// `shading` gives the ambient occlusion and light of each corner. The corners are in the order: the start of the
+    /// rectangle, then anticlockwise around the tangents' cross product.
+    ///
+    /// Fails if the mesh would contain more vertices than a 32-bit index can address.
+    pub fn add_face(
+        &mut self, pos: glam::Vec3, face: Face, size: UVec2, texture: u32, transparent: bool, shading: FaceShading
+    ) -> Result<(), Error> {
+        let start = first_index(self.vertices.len())?;
+
+        let normal = face.normal().as_vec3();
+        let (a, b) = face.tangents();
+        let (a, b) = (a.as_vec3() * size.x as f32, b.as_vec3() * size.y as f32);
+
+        // the corner of the first block that lies on the plane of the face
+        let base = pos - Vec3::splat(0.5) + normal.max(Vec3::ZERO);
+        let corners = [base, base + a, base + a + b, base + b];
+        let centre = base + (a + b) * 0.5;
+
+        let (u_axis, v_axis) = face.texture_axes();
+        let extent = vec2((a + b).dot(u_axis).abs(), (a + b).dot(v_axis).abs());
+
+        let FaceShading { occlusion, light } = shading;
+
+        self.vertices.extend((0..4).map(|i| Vertex {
+            position: corners[i],
+            uv: vec2((corners[i] - centre).dot(u_axis), (corners[i] - centre).dot(v_axis)) + extent * 0.5,
+            texture,
+            occlusion: occlusion[i] as f32 / MAX_OCCLUSION as f32,
+            light: vec2(light[i].sky() as f32, light[i].block() as f32) / MAX_LIGHT as f32
+        }));
+
+        // split the quad along whichever diagonal is less occluded; otherwise the occlusion of a single corner gets
+        // stretched across the whole quad
+        let triangles = if occlusion[0] + occlusion[2] >= occlusion[1] + occlusion[3] {
+            [[0, 1, 2], [0, 2, 3]]
+        } else {
+            [[0, 1, 3], [1, 2, 3]]
+        };
+
+        // corners are wound anticlockwise around the axis a x b; flip them if that points into the cube
+        let flip = a.cross(b).dot(normal) < 0.0;
+
+        let indices = if transparent { &mut self.transparent_indices } else { &mut self.indices };
+
+        for [i, j, k] in triangles {
+            let (j, k) = if flip { (k, j) } else { (j, k) };
+            indices.extend_from_slice(&[start + i, start + j, start + k]);
         }
+
+        Ok(())
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn build(self) -> MeshData {
+        MeshData { vertices: self.vertices, indices: self.indices, transparent_indices: self.transparent_indices }
     }
+}
 
-    pub fn build(self, device: &wgpu::Device) -> Mesh {
-        Mesh::new(device, &self.vertices, &self.indices)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_format_boundary() {
+        assert_eq!(index_format(0), wgpu::IndexFormat::Uint16);
+        assert_eq!(index_format(65_536), wgpu::IndexFormat::Uint16);
+        assert_eq!(index_format(65_537), wgpu::IndexFormat::Uint32);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn too_many_vertices() {
+        let last = u32::MAX as usize - 3;
+
+        assert_eq!(first_index(last).unwrap(), u32::MAX - 3);
+        assert!(matches!(first_index(last + 1), Err(Error::MeshTooLarge(count)) if count == last + 1));
+        assert!(matches!(first_index(u32::MAX as usize + 1), Err(Error::MeshTooLarge(_))));
+    }
+}
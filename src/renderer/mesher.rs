@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, mpsc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+
+use glam::IVec3;
+
+use crate::world::{World, BlockRegistry, PaddedChunk};
+
+use super::{MeshData, Error};
+
+/// A request to mesh a snapshot of a chunk
+struct Job {
+    pos: IVec3,
+    generation: u64,
+    chunk: PaddedChunk,
+    registry: Arc<BlockRegistry>,
+    /// Set when a newer job for the same chunk is submitted, so that workers can skip this one
+    cancelled: Arc<AtomicBool>
+}
+
+struct Finished {
+    pos: IVec3,
+    generation: u64,
+    mesh: Result<MeshData, Error>
+}
+
+/// A job that has been submitted to the [Mesher], but whose result has not been collected yet
+struct Pending {
+    generation: u64,
+    cancelled: Arc<AtomicBool>
+}
+
+/// A pool of worker threads that turn chunk snapshots into mesh data, off the render thread.
+pub struct Mesher {
+    jobs: Option<mpsc::Sender<Job>>,
+    results: mpsc::Receiver<Finished>,
+    workers: Vec<JoinHandle<()>>,
+    pending: HashMap<IVec3, Pending>,
+    next_generation: u64
+}
+
+impl Mesher {
+    /// Starts a mesher with `threads` worker threads
+    pub fn new(threads: usize) -> Mesher {
+        let (job_sender, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..threads.max(1))
+            .map(|i| {
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+
+                std::thread::Builder::new()
+                    .name(format!("mesher {}", i))
+                    .spawn(move || Mesher::work(&jobs, &results))
+                    .expect("failed to spawn mesher thread")
+            })
+            .collect();
+
+        Mesher { jobs: Some(job_sender), results, workers, pending: HashMap::new(), next_generation: 0 }
+    }
+
+    fn work(jobs: &Mutex<mpsc::Receiver<Job>>, results: &mpsc::Sender<Finished>) {
+        loop {
+            // the lock guard is dropped at the end of this statement, so other workers can take jobs while we mesh
+            let job = match jobs.lock().expect("mesher job queue poisoned").recv() {
+                Ok(job) => job,
+                // the mesher has been dropped
+                Err(_) => return
+            };
+
+            if job.cancelled.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let mesh = job.chunk.build_mesh(World::chunk_origin(job.pos), &job.registry);
+
+            if job.cancelled.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            if results.send(Finished { pos: job.pos, generation: job.generation, mesh }).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Queues a chunk to be meshed, cancelling any job for the same chunk that has not finished yet
+    pub fn submit(&mut self, pos: IVec3, chunk: PaddedChunk, registry: Arc<BlockRegistry>) {
+        self.cancel(pos);
+
+        let generation = self.next_generation;
+        self.next_generation += 1;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.pending.insert(pos, Pending { generation, cancelled: cancelled.clone() });
+
+        let job = Job { pos, generation, chunk, registry, cancelled };
+
+        self.jobs.as_ref()
+            .expect("mesher has shut down")
+            .send(job)
+            .expect("all mesher threads have exited");
+    }
+
+    /// Cancels the pending job for a chunk, if there is one
+    pub fn cancel(&mut self, pos: IVec3) {
+        if let Some(pending) = self.pending.remove(&pos) {
+            pending.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether every submitted job has finished and had its result collected
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns the next finished mesh, if any. Results of jobs that have since been cancelled are skipped.
+    pub fn poll(&mut self) -> Option<(IVec3, Result<MeshData, Error>)> {
+        while let Ok(finished) = self.results.try_recv() {
+            let current = self.pending.get(&finished.pos)
+                .is_some_and(|pending| pending.generation == finished.generation);
+
+            if current {
+                self.pending.remove(&finished.pos);
+                return Some((finished.pos, finished.mesh));
+            }
+        }
+
+        None
+    }
+}
+
+impl Drop for Mesher {
+    fn drop(&mut self) {
+        for pending in self.pending.values() {
+            pending.cancelled.store(true, Ordering::Relaxed);
+        }
+
+        // closing the job queue makes each worker exit once it is idle
+        self.jobs.take();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use glam::ivec3;
+
+    use super::*;
+    use crate::world::{BlockId, Chunk, blocks};
+
+    #[test]
+    fn resubmitting_keeps_only_the_newest_mesh() {
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+        let mut stone = Chunk::new();
+        stone.set(ivec3(1, 2, 3), blocks::STONE);
+        world.insert_chunk(IVec3::ZERO, stone);
+
+        let mut mesher = Mesher::new(2);
+        mesher.submit(IVec3::ZERO, PaddedChunk::from_world(&world, IVec3::ZERO).unwrap(), world.registry().clone());
+
+        // resubmitted before the first result is collected, now with nothing to mesh
+        world.set_block(ivec3(1, 2, 3), BlockId::AIR);
+        mesher.submit(IVec3::ZERO, PaddedChunk::from_world(&world, IVec3::ZERO).unwrap(), world.registry().clone());
+        assert!(!mesher.is_idle());
+
+        let start = Instant::now();
+        let (pos, mesh) = loop {
+            if let Some(result) = mesher.poll() {
+                break result;
+            }
+
+            assert!(start.elapsed() < Duration::from_secs(10), "mesher never finished");
+            std::thread::yield_now();
+        };
+
+        assert_eq!(pos, IVec3::ZERO);
+        assert!(mesh.unwrap().vertices.is_empty());
+        assert!(mesher.is_idle());
+
+        // whether or not the first job ran, its result is never returned
+        assert!(mesher.poll().is_none());
+    }
+}
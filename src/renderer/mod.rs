@@ -2,11 +2,21 @@ pub mod mesh;
 pub mod shader;
 pub mod camera;
 pub mod texture;
+pub mod mesher;
+pub mod outline;
+pub mod target;
+pub mod frustum;
 
 pub use mesh::*;
 pub use shader::*;
 pub use camera::*;
 pub use texture::*;
+pub use mesher::*;
+pub use outline::*;
+pub use target::*;
+pub use frustum::*;
+
+use std::collections::HashMap;
 
 use winit::{
     window::Window,
@@ -15,69 +25,95 @@ use winit::{
 
 use glam::*;
 
-use crate::world::Chunk;
+use crate::world::{World, PaddedChunk, BlockRegistry};
+
+/// The directory debug builds load block textures from, as `<texture name>.png`, so that they can be changed without
+/// recompiling
+const BLOCK_TEXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/res/blocks");
+
+/// The contents of [BLOCK_TEXTURE_DIR] as `(texture name, PNG bytes)`, compiled in by the build script so that
+/// release builds don't depend on the source tree
+const EMBEDDED_BLOCK_TEXTURES: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/block_textures.rs"));
+
+/// The shader blocks are drawn with, which debug builds load from here rather than embedding, so that it can be edited
+/// while running
+const BLOCK_SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/shader.wgsl");
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("no valid GPU was found; fatal")]
-    NoAdapterFound
+    NoAdapterFound,
+    #[error("mesh has too many vertices to index ({0} already added)")]
+    MeshTooLarge(usize),
+    #[error("cannot read back textures in the {0:?} format")]
+    UnsupportedReadbackFormat(wgpu::TextureFormat),
+    #[error("only offscreen render targets can be read back")]
+    NotOffscreen,
+    #[error("texture was not created with COPY_SRC usage, so cannot be read back")]
+    NotCopySource,
+    #[error("invalid shader at {0}")]
+    InvalidShader(String)
+}
+
+/// Counts of what was drawn in a frame
+#[derive(Copy, Clone, Default, Debug)]
+pub struct RenderStats {
+    /// Chunk meshes that were in view, and drawn
+    pub visible: usize,
+    /// Chunk meshes that were skipped, either for being out of view or empty
+    pub culled: usize
 }
 
-/// The [Renderer] is responsible for managing the GPU, and rendering to the window.
+/// The [Renderer] is responsible for managing the GPU, and rendering to a window or an offscreen texture.
 pub struct Renderer {
-    window: Window,
-    _window_size: PhysicalSize<u32>,
-    surface: wgpu::Surface,
+    target: RenderTarget,
+    /// The size of the render target; may be zero while the window is minimised
+    size: PhysicalSize<u32>,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    _config: wgpu::SurfaceConfiguration,
     shader: Shader,
+    /// Draws the faces of blocks that can be seen through, after everything opaque
+    transparent: Shader,
+    /// Draws the world's edges instead of its faces, if the device supports it
+    wireframe: Option<Shader>,
+    /// Whether to draw the world with [wireframe](Renderer::wireframe)
+    show_wireframe: bool,
     pub camera: Camera,
-    texture: Texture,
+    /// Every block texture, one per array layer, indexed by [TextureId](crate::world::TextureId)
+    block_textures: Texture,
     depth_buffer: Texture,
-    mesh: Mesh
+    /// One mesh for each chunk of the world that has been uploaded, keyed by chunk coordinate
+    meshes: HashMap<IVec3, Mesh>,
+    mesher: Mesher,
+    /// Highlights the block the player is aiming at
+    pub outline: Outline,
+    /// The maximum number of chunk meshes uploaded to the GPU each frame
+    pub upload_budget: usize,
+    /// What was drawn in the last frame
+    stats: RenderStats,
+    /// Whether to copy the next frame for a screenshot
+    screenshot_requested: bool,
+    /// Frames being copied back from the GPU, to be collected with [Renderer::take_screenshots]
+    screenshots: Vec<Readback>
 }
 
 impl Renderer {
-    pub fn window(&self) -> &Window { &self.window }
+    /// The window being drawn to, or `None` for a headless renderer
+    pub fn window(&self) -> Option<&Window> { self.target.window() }
 
-    pub fn init(window: Window) -> anyhow::Result<Renderer> {
-        let window_size = window.inner_size();
+    /// Creates a renderer drawing to `window`, loading the textures used by the blocks in `registry`
+    pub fn init(window: Window, registry: &BlockRegistry) -> anyhow::Result<Renderer> {
+        let size = window.inner_size();
 
         log::info!("creating WGPU context");
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default()
-        });
+        let instance = Renderer::instance();
     
         log::info!("get surface from window");
         // unsafe as surface must live as long as window it was created from; fine as renderer owns both Window and Surface
-        let surface = unsafe { instance.create_surface(&window) }?;
+        let mut surface = unsafe { instance.create_surface(&window) }?;
     
         log::info!("get handle to graphics card");
-        let (adapter, device, queue) = pollster::block_on(async {
-            // note: this will not work for all devices; may need to enumerate adapters later
-            let adapter = instance.request_adapter(
-                &wgpu::RequestAdapterOptions {
-                    power_preference: wgpu::PowerPreference::default(),
-                    compatible_surface: Some(&surface),
-                    force_fallback_adapter: false
-                }
-            ).await.ok_or(Error::NoAdapterFound)?;
-
-            let (device, queue) = adapter.request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::POLYGON_MODE_LINE,
-                    limits: wgpu::Limits::default(),
-                    label: None
-                },
-                None
-            ).await?;
-
-            // ugly, but need to make error type explicit
-            // TODO: migrate to own error type to avoid allocation, if it becomes an issue
-            Ok::<_, anyhow::Error>((adapter, device, queue))
-        })?;
+        let (adapter, device, queue) = Renderer::request_device(&instance, Some(&surface), false)?;
 
         let capabilities = surface.get_capabilities(&adapter);
 
@@ -86,11 +122,17 @@ impl Renderer {
             .find(|f| f.is_srgb())
             .unwrap_or(capabilities.formats[0]);
 
+        // screenshots copy the frame straight from the surface where they can
+        let usage = match surface_supports_copy(&adapter, &mut surface) {
+            true => wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            false => wgpu::TextureUsages::RENDER_ATTACHMENT
+        };
+
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage,
             format: surface_format,
-            width: window_size.width,
-            height: window_size.height,
+            width: size.width,
+            height: size.height,
             present_mode: capabilities.present_modes[0],
             alpha_mode: capabilities.alpha_modes[0],
             view_formats: vec![]
@@ -98,45 +140,242 @@ impl Renderer {
 
         surface.configure(&device, &config);
 
-        let chunk = Chunk::new();
+        Renderer::new(RenderTarget::Window { window, surface, config }, size, &adapter, device, queue, registry)
+    }
+
+    /// Creates a renderer that draws into an offscreen texture of the given size, which can be read back with
+    /// [Renderer::read_pixels]. This needs no window or display, and prefers a software adapter so that it also
+    /// works on machines without a GPU.
+    pub fn headless(size: PhysicalSize<u32>, registry: &BlockRegistry) -> anyhow::Result<Renderer> {
+        let instance = Renderer::instance();
+
+        let (adapter, device, queue) = Renderer::request_device(&instance, None, true)
+            .or_else(|_| Renderer::request_device(&instance, None, false))?;
+
+        log::info!("rendering headless with {:?}", adapter.get_info());
+
+        let target = RenderTarget::offscreen(&device, size);
+        Renderer::new(target, size, &adapter, device, queue, registry)
+    }
+
+    fn instance() -> wgpu::Instance {
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: Default::default()
+        })
+    }
+
+    fn request_device(
+        instance: &wgpu::Instance, surface: Option<&wgpu::Surface>, fallback: bool
+    ) -> anyhow::Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+        pollster::block_on(async {
+            // note: this will not work for all devices; may need to enumerate adapters later
+            let adapter = instance.request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: surface,
+                    force_fallback_adapter: fallback
+                }
+            ).await.ok_or(Error::NoAdapterFound)?;
+
+            let (device, queue) = adapter.request_device(
+                &wgpu::DeviceDescriptor {
+                    // only used for debugging, so not worth failing over on adapters without it
+                    features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
+                    limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+                    label: None
+                },
+                None
+            ).await?;
 
-        let mesh = chunk.build_mesh(&device);
+            // ugly, but need to make error type explicit
+            // TODO: migrate to own error type to avoid allocation, if it becomes an issue
+            Ok::<_, anyhow::Error>((adapter, device, queue))
+        })
+    }
 
+    /// Sets up everything that doesn't depend on what is being rendered to
+    fn new(
+        target: RenderTarget, size: PhysicalSize<u32>, adapter: &wgpu::Adapter, device: wgpu::Device, queue: wgpu::Queue,
+        registry: &BlockRegistry
+    ) -> anyhow::Result<Renderer> {
         let camera = Camera::new(
             &device,
             (0.0, 1.0, 2.0).into(),
             0.0, 0.0,
             90.0,
-            1280.0 / 720.0,
-            0.1, 100.0
+            size.width as f32 / size.height.max(1) as f32,
+            0.1, 250.0
         );
 
-        let image = image::load_from_memory(include_bytes!("../res/test.png"))?;
-        let texture = Texture::from_image(&device, &queue, &image, "test texture");
-        let depth_buffer = Texture::new_depth_buffer(&device, &config);
+        log::info!("loading {} block textures", registry.texture_names().len());
+        let layers = load_layers(registry.texture_names(), load_block_texture);
+        let block_textures = Texture::from_layers(&device, &queue, &layers, &block_sampler(adapter), "block textures");
+        let depth_buffer = Texture::new_depth_buffer(&device, size);
 
-        let shader = Shader::from_source(&device, &config, &[camera.bind_group_layout(), texture.bind_group_layout()], "test_shader", include_str!("../shaders/shader.wgsl"));
+        // leave a core free for the render thread
+        let mesher_threads = std::thread::available_parallelism()
+            .map(|n| n.get().saturating_sub(1))
+            .unwrap_or(1);
+
+        let layouts = [camera.bind_group_layout(), block_textures.bind_group_layout()];
+
+        let shader = Shader::load(
+            &device, target.format(), &layouts, &PipelineDescriptor::opaque(), "test_shader",
+            BLOCK_SHADER_PATH, include_str!("../shaders/shader.wgsl")
+        );
+
+        let transparent = Shader::load(
+            &device, target.format(), &layouts, &PipelineDescriptor::transparent(), "transparent",
+            BLOCK_SHADER_PATH, include_str!("../shaders/shader.wgsl")
+        );
+
+        let wireframe = device.features().contains(wgpu::Features::POLYGON_MODE_LINE)
+            .then(|| Shader::load(
+                &device, target.format(), &layouts, &PipelineDescriptor::wireframe(), "wireframe",
+                BLOCK_SHADER_PATH, include_str!("../shaders/shader.wgsl")
+            ));
+
+        let outline = Outline::new(&device, target.format(), camera.bind_group_layout());
 
         Ok(Renderer {
-            window, _window_size: window_size, surface, device, queue, _config: config, shader, camera, texture, depth_buffer, mesh
+            target, size, device, queue, shader, transparent, wireframe, camera, block_textures, depth_buffer,
+            show_wireframe: false,
+            meshes: HashMap::new(),
+            mesher: Mesher::new(mesher_threads),
+            outline,
+            upload_budget: 8,
+            stats: RenderStats::default(),
+            screenshot_requested: false,
+            screenshots: vec![]
         })
     }
 
+    /// Resizes the render target and depth buffer, and matches the camera's aspect ratio to the new size. A zero size,
+    /// such as when the window is minimised, is remembered but not applied until it has a size again.
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.size = size;
+
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+
+        log::debug!("resizing render target to {}x{}", size.width, size.height);
+
+        self.target.resize(&self.device, size);
+
+        self.depth_buffer = Texture::new_depth_buffer(&self.device, size);
+        self.camera.aspect = size.width as f32 / size.height as f32;
+    }
+
+    /// Queues the chunk at `pos` to be remeshed in the background, or drops its mesh if that chunk is no longer
+    /// part of the world
+    pub fn update_chunk(&mut self, world: &World, pos: IVec3) {
+        match PaddedChunk::from_world(world, pos) {
+            Some(chunk) => self.mesher.submit(pos, chunk, world.registry().clone()),
+            None => {
+                self.mesher.cancel(pos);
+                self.meshes.remove(&pos);
+            }
+        }
+    }
+
+    /// Uploads chunk meshes that have finished building, up to the [upload budget](Renderer::upload_budget)
+    pub fn upload_meshes(&mut self) {
+        for _ in 0..self.upload_budget {
+            let Some((pos, mesh)) = self.mesher.poll() else {
+                break;
+            };
+
+            match mesh {
+                Ok(data) => {
+                    self.meshes.insert(pos, Mesh::new(&self.device, &data));
+                },
+                Err(e) => log::error!("failed to mesh chunk at {}: {}", pos, e)
+            }
+        }
+    }
+
+    /// Blocks until every chunk queued with [Renderer::update_chunk] has been meshed and uploaded, for when a complete
+    /// frame matters more than a smooth one
+    pub fn finish_meshing(&mut self) {
+        while !self.mesher.is_idle() {
+            self.upload_meshes();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
     pub fn render(&mut self) -> anyhow::Result<()> {
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // nothing to draw into while minimised
+        if self.size.width == 0 || self.size.height == 0 {
+            return Ok(());
+        }
+
+        let frame = match self.target.frame() {
+            Ok(frame) => frame,
+            // the surface no longer matches the window, so set it up again and try next frame
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                log::debug!("surface lost or outdated; reconfiguring");
+                self.resize(self.size);
+                return Ok(());
+            },
+            Err(e) => return Err(e.into())
+        };
+
+        self.shader.reload_if_changed(&self.device);
+        self.transparent.reload_if_changed(&self.device);
+        if let Some(wireframe) = &mut self.wireframe {
+            wireframe.reload_if_changed(&self.device);
+        }
 
+        self.camera.update(&self.queue);
+        self.outline.update(&self.queue);
+
+        let screenshot = std::mem::take(&mut self.screenshot_requested);
+
+        // Metal and GL surfaces, and some Vulkan drivers', can't be copied from, so there the frame is drawn a second
+        // time into a texture that can be. This happens first so that the stats are left describing the real frame.
+        if screenshot && !self.target.can_copy_frames() {
+            let texture = offscreen_texture(&self.device, self.size, self.target.format());
+            self.draw(&texture.create_view(&wgpu::TextureViewDescriptor::default()));
+            let readback = Readback::start(&self.device, &self.queue, &texture);
+            self.add_screenshot(readback);
+        }
+
+        self.draw(&frame.view);
+
+        // the frame has to be copied before it is presented, after which its texture can no longer be used
+        if screenshot && self.target.can_copy_frames() {
+            let readback = Readback::start(&self.device, &self.queue, frame.texture(&self.target));
+            self.add_screenshot(readback);
+        }
+
+        frame.present();
+
+        Ok(())
+    }
+
+    fn add_screenshot(&mut self, readback: Result<Readback, Error>) {
+        match readback {
+            Ok(readback) => self.screenshots.push(readback),
+            Err(e) => log::error!("failed to take screenshot: {}", e)
+        }
+    }
+
+    /// Draws the world into `view`, which must have the render target's size and format
+    fn draw(&mut self, view: &wgpu::TextureView) {
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder")
         });
 
-        self.camera.update(&self.queue);
+        // visible meshes with transparent faces, to draw once everything opaque has been
+        let mut transparent = vec![];
 
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -159,29 +398,204 @@ impl Renderer {
             });
             
             render_pass.bind_resource(0, &self.camera);
-            render_pass.bind_resource(1, &self.texture);
-            render_pass.use_shader(&self.shader);
-            render_pass.draw_mesh(&self.mesh);
+            render_pass.bind_resource(1, &self.block_textures);
+
+            // wireframes show every face the same way, so transparent faces are drawn along with the rest
+            let wireframe = self.wireframe.as_ref().filter(|_| self.show_wireframe);
+            render_pass.use_shader(wireframe.unwrap_or(&self.shader));
+
+            let frustum = self.camera.frustum();
+            self.stats = RenderStats::default();
+
+            for mesh in self.meshes.values() {
+                if !mesh.bounds.is_some_and(|bounds| frustum.intersects(&bounds)) {
+                    self.stats.culled += 1;
+                    continue;
+                }
+
+                self.stats.visible += 1;
+
+                if wireframe.is_some() {
+                    render_pass.draw_mesh(mesh, mesh.opaque_indices.start..mesh.transparent_indices.end);
+                } else {
+                    render_pass.draw_mesh(mesh, mesh.opaque_indices.clone());
+
+                    if !mesh.transparent_indices.is_empty() {
+                        transparent.push(mesh);
+                    }
+                }
+            }
+        }
+
+        // transparent faces don't hide what is behind them, so chunks are drawn furthest first for each to blend over
+        // the ones behind it. Faces within a chunk aren't sorted.
+        let distance = |mesh: &Mesh| mesh.bounds.map_or(0.0, |bounds| {
+            ((bounds.min + bounds.max) * 0.5).distance_squared(self.camera.position)
+        });
+        transparent.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+
+        // drawn in a second pass on top of the opaque world, testing against its depth buffer without writing to it
+        {
+            let mut overlay_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Transparent Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true
+                    }
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: self.depth_buffer.view(),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: false
+                    }),
+                    stencil_ops: None
+                })
+            });
+
+            overlay_pass.bind_resource(0, &self.camera);
+
+            if !transparent.is_empty() {
+                overlay_pass.bind_resource(1, &self.block_textures);
+                overlay_pass.use_shader(&self.transparent);
+
+                for mesh in transparent {
+                    overlay_pass.draw_mesh(mesh, mesh.transparent_indices.clone());
+                }
+            }
+
+            self.outline.draw(&mut overlay_pass);
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    }
 
-        Ok(())
+    /// Switches between drawing the world's faces and its edges. Does nothing if the device can't draw wireframes.
+    pub fn toggle_wireframe(&mut self) {
+        if self.wireframe.is_none() {
+            log::warn!("wireframe rendering is not supported by this device");
+            return;
+        }
+
+        self.show_wireframe = !self.show_wireframe;
+        log::info!("wireframe {}", if self.show_wireframe { "on" } else { "off" });
+    }
+
+    /// What was drawn in the last frame
+    pub fn stats(&self) -> RenderStats { self.stats }
+
+    /// Copies the next frame drawn, which can be collected with [Renderer::take_screenshots] once it has been read
+    /// back from the GPU
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    /// Returns every screenshot that has finished being read back since the last call, or failed to, without waiting
+    /// for the rest
+    pub fn take_screenshots(&mut self) -> Vec<anyhow::Result<image::RgbaImage>> {
+        if self.screenshots.is_empty() {
+            return vec![];
+        }
+
+        self.device.poll(wgpu::Maintain::Poll);
+
+        let mut finished = vec![];
+        self.screenshots.retain(|readback| match readback.try_finish() {
+            None => true,
+            Some(result) => {
+                finished.push(result);
+                false
+            }
+        });
+
+        finished
+    }
+
+    /// Reads back the last frame drawn by a [headless](Renderer::headless) renderer
+    pub fn read_pixels(&self) -> anyhow::Result<image::RgbaImage> {
+        match &self.target {
+            RenderTarget::Offscreen { texture } => read_texture(&self.device, &self.queue, texture),
+            RenderTarget::Window { .. } => Err(Error::NotOffscreen.into())
+        }
     }
 }
 
+/// Loads a block texture from [BLOCK_TEXTURE_DIR] in debug builds, or from [EMBEDDED_BLOCK_TEXTURES] in release builds.
+/// Debug builds also fall back to the embedded texture if the file can't be loaded.
+fn load_block_texture(name: &str) -> anyhow::Result<image::RgbaImage> {
+    if cfg!(debug_assertions) {
+        match load_png(std::path::Path::new(BLOCK_TEXTURE_DIR), name) {
+            Ok(image) => return Ok(image),
+            Err(e) => log::warn!(
+                "failed to load block texture {} from {} ({}); using the copy built into the binary",
+                name, BLOCK_TEXTURE_DIR, e
+            )
+        }
+    }
+
+    let (_, bytes) = EMBEDDED_BLOCK_TEXTURES.iter()
+        .find(|(embedded, _)| *embedded == name)
+        .ok_or_else(|| anyhow::anyhow!("no texture is embedded with that name"))?;
+
+    Ok(image::load_from_memory_with_format(bytes, image::ImageFormat::Png)?.to_rgba8())
+}
+
+/// Creates a headless renderer for a test. Tests that need a GPU fail when no adapter can be created, even a software
+/// one, unless `VOXEL_SKIP_GPU_TESTS` is set; then this returns `None` and the test should return early.
+#[cfg(test)]
+fn test_renderer(size: PhysicalSize<u32>, registry: &BlockRegistry) -> Option<Renderer> {
+    match Renderer::headless(size, registry) {
+        Ok(renderer) => Some(renderer),
+        Err(e) if std::env::var_os("VOXEL_SKIP_GPU_TESTS").is_some() => {
+            eprintln!("skipping test, as no adapter could be created: {}", e);
+            None
+        },
+        Err(e) => panic!("no adapter could be created ({}); install a software Vulkan or GL driver such as lavapipe or \
+            llvmpipe, or set VOXEL_SKIP_GPU_TESTS=1 to skip tests that need one", e)
+    }
+}
+
+/// How block textures are filtered. Setting `VOXEL_ANISOTROPY` to a level from 2 to 16 switches to smooth, anisotropic
+/// filtering, if the adapter supports it.
+fn block_sampler(adapter: &wgpu::Adapter) -> SamplerSettings {
+    let Ok(anisotropy) = std::env::var("VOXEL_ANISOTROPY") else {
+        return SamplerSettings::default();
+    };
+
+    let Ok(anisotropy) = anisotropy.parse::<u16>() else {
+        log::warn!("ignoring invalid VOXEL_ANISOTROPY '{}'", anisotropy);
+        return SamplerSettings::default();
+    };
+
+    let supported = adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING);
+    if !supported {
+        log::warn!("adapter does not support anisotropic filtering");
+        return SamplerSettings::default();
+    }
+
+    log::info!("using {}x anisotropic filtering", anisotropy);
+    SamplerSettings::anisotropic(anisotropy)
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     pub position: Vec3,
-    pub uv: Vec2
+    pub uv: Vec2,
+    pub texture: u32,
+    /// How much light reaches this vertex past the blocks around it, from 0 to 1
+    pub occlusion: f32,
+    /// The sky light and block light levels at this vertex, from 0 to 1
+    pub light: Vec2
 }
 
 impl Vertex {
     pub fn layout() -> wgpu::VertexBufferLayout<'static> {
-        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
-            0 => Float32x3, 1 => Float32x2
+        const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+            0 => Float32x3, 1 => Float32x2, 2 => Uint32, 3 => Float32, 4 => Float32x2
         ];
 
         wgpu::VertexBufferLayout {
@@ -193,15 +607,16 @@ impl Vertex {
 }
 
 trait DrawMesh<'a> {
-    fn draw_mesh(&mut self, mesh: &'a Mesh);
+    /// Draws the triangles of `mesh` in the range `indices` of its index buffer
+    fn draw_mesh(&mut self, mesh: &'a Mesh, indices: std::ops::Range<u32>);
 }
 
 impl<'a, 'b> DrawMesh<'a> for wgpu::RenderPass<'b>
 where 'a: 'b {
-    fn draw_mesh(&mut self, mesh: &'a Mesh) {
+    fn draw_mesh(&mut self, mesh: &'a Mesh, indices: std::ops::Range<u32>) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        self.draw_indexed(0..mesh.index_count, 0, 0..1);
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+        self.draw_indexed(indices, 0, 0..1);
     }
 }
 
@@ -220,4 +635,29 @@ where 'a: 'b {
     fn bind_resource(&mut self, index: u32, resource: &'a impl GpuResource) {
         self.set_bind_group(index, resource.bind_group(), &[]);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_textures_are_embedded() {
+        let registry = BlockRegistry::with_default_blocks();
+
+        for name in registry.texture_names() {
+            let (_, bytes) = EMBEDDED_BLOCK_TEXTURES.iter()
+                .find(|(embedded, _)| embedded == name)
+                .unwrap_or_else(|| panic!("texture '{}' is not embedded", name));
+
+            let embedded = image::load_from_memory(bytes).unwrap().to_rgba8();
+            assert_eq!(embedded, load_png(std::path::Path::new(BLOCK_TEXTURE_DIR), name).unwrap());
+        }
+
+        // every texture in the directory is embedded, not just the ones the default blocks use
+        let files = std::fs::read_dir(BLOCK_TEXTURE_DIR).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|extension| extension == "png"))
+            .count();
+        assert_eq!(EMBEDDED_BLOCK_TEXTURES.len(), files);
+    }
+}
@@ -0,0 +1,137 @@
+use wgpu::util::DeviceExt;
+
+use glam::*;
+
+use super::{Shader, PipelineDescriptor, DepthTest, UseShader};
+
+/// How far the outline sits outside the faces of the block, so that it isn't hidden by them in the depth buffer
+const INFLATE: f32 = 0.005;
+
+/// Each vertex is just a position
+const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+
+/// Draws the edges of a single block, to show which block the player is aiming at. Uses its own line list pipeline,
+/// which tests against the depth buffer of the world pass, but doesn't write to it.
+pub struct Outline {
+    shader: Shader,
+    vertex_buffer: wgpu::Buffer,
+    vertex_count: u32,
+    uniform: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// The block to outline, if any
+    pub target: Option<IVec3>
+}
+
+impl Outline {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, camera_layout: &wgpu::BindGroupLayout) -> Outline {
+        let vertices = Outline::edges();
+
+        let vertex_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("outline vertices"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX
+            }
+        );
+
+        let uniform = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("outline uniform"),
+                mapped_at_creation: false,
+                size: std::mem::size_of::<Vec4>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
+            }
+        );
+
+        let layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: Some("outline"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None
+                        },
+                        count: None
+                    }
+                ]
+            }
+        );
+
+        let bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("outline"),
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform.as_entire_binding()
+                    }
+                ]
+            }
+        );
+
+        let descriptor = PipelineDescriptor {
+            vertex_layouts: vec![wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<Vec3>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &ATTRIBUTES
+            }],
+            topology: wgpu::PrimitiveTopology::LineList,
+            cull_mode: None,
+            // test against the world so walls hide the outline, but don't write; the outline is drawn last
+            depth: Some(DepthTest { write: false, compare: wgpu::CompareFunction::LessEqual }),
+            ..PipelineDescriptor::opaque()
+        };
+
+        let shader = Shader::from_source(
+            device, format, &[camera_layout, &layout], &descriptor, "outline", include_str!("../shaders/outline.wgsl")
+        );
+
+        Outline { shader, vertex_buffer, vertex_count: vertices.len() as u32, uniform, bind_group, target: None }
+    }
+
+    /// The twelve edges of a slightly enlarged block centred on the origin, as pairs of points
+    fn edges() -> Vec<Vec3> {
+        let h = 0.5 + INFLATE;
+        let corner = |i: u32| vec3(
+            if i & 1 == 0 { -h } else { h },
+            if i & 2 == 0 { -h } else { h },
+            if i & 4 == 0 { -h } else { h }
+        );
+
+        // corners joined by an edge differ along exactly one axis, i.e. in one bit of their index
+        let mut edges = vec![];
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    edges.push(corner(i));
+                    edges.push(corner(i | axis));
+                }
+            }
+        }
+
+        edges
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        if let Some(target) = self.target {
+            queue.write_buffer(&self.uniform, 0, bytemuck::cast_slice(&[target.as_vec3().extend(0.0)]));
+        }
+    }
+
+    /// Draws the outline, if there is a target. The camera must already be bound to group 0.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        if self.target.is_none() {
+            return;
+        }
+
+        pass.use_shader(&self.shader);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..self.vertex_count, 0..1);
+    }
+}
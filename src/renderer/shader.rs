@@ -1,79 +1,322 @@
 use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
 
-use super::Vertex;
+use super::{Vertex, Texture, Error};
+
+/// How often shaders loaded from files are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How a [Shader]'s pipeline draws: everything about it besides the shader code and what it binds. Start from one of
+/// the presets and override what differs, e.g. `PipelineDescriptor { cull_mode: None, ..PipelineDescriptor::opaque() }`.
+#[derive(Clone, Debug)]
+pub struct PipelineDescriptor {
+    /// The layout of each vertex buffer, by slot
+    pub vertex_layouts: Vec<wgpu::VertexBufferLayout<'static>>,
+    pub vertex_entry_point: &'static str,
+    pub fragment_entry_point: &'static str,
+    pub topology: wgpu::PrimitiveTopology,
+    /// Which side of each triangle is skipped, with triangles wound anticlockwise facing the camera
+    pub cull_mode: Option<wgpu::Face>,
+    /// Anything but [Fill](wgpu::PolygonMode::Fill) needs a device feature
+    pub polygon_mode: wgpu::PolygonMode,
+    /// How output is combined with what has already been drawn, or `None` to overwrite it
+    pub blend: Option<wgpu::BlendState>,
+    /// How the depth buffer is used, or `None` to draw without one
+    pub depth: Option<DepthTest>,
+    /// Must match the number of samples in the textures being drawn into
+    pub sample_count: u32
+}
+
+/// How a pipeline uses the depth buffer
+#[derive(Copy, Clone, Debug)]
+pub struct DepthTest {
+    /// Whether drawing updates the depth buffer, so that later draws are hidden behind it
+    pub write: bool,
+    /// When a fragment is drawn, by comparing its depth to the buffer's
+    pub compare: wgpu::CompareFunction
+}
+
+impl PipelineDescriptor {
+    /// Solid [Vertex] geometry, such as the world's blocks, hiding whatever is behind it
+    pub fn opaque() -> PipelineDescriptor {
+        PipelineDescriptor {
+            vertex_layouts: vec![Vertex::layout()],
+            vertex_entry_point: "vs_main",
+            fragment_entry_point: "fs_main",
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            blend: None,
+            depth: Some(DepthTest { write: true, compare: wgpu::CompareFunction::Less }),
+            sample_count: 1
+        }
+    }
+
+    /// Alpha blended [Vertex] geometry, drawn after everything opaque. It is hidden by opaque geometry but doesn't
+    /// write depth, so it should be drawn back to front.
+    pub fn transparent() -> PipelineDescriptor {
+        PipelineDescriptor {
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            depth: Some(DepthTest { write: false, compare: wgpu::CompareFunction::Less }),
+            ..PipelineDescriptor::opaque()
+        }
+    }
+
+    /// The edges of [Vertex] geometry's triangles, from both sides. Needs the
+    /// [POLYGON_MODE_LINE](wgpu::Features::POLYGON_MODE_LINE) feature.
+    pub fn wireframe() -> PipelineDescriptor {
+        PipelineDescriptor {
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Line,
+            ..PipelineDescriptor::opaque()
+        }
+    }
+}
+
+/// Where a shader's source comes from
+enum Source {
+    /// Compiled into the binary, so never changes
+    Embedded,
+    /// Read from a file, which is watched for changes
+    File {
+        path: PathBuf,
+        /// A hash of the file's contents when it was last read, whether or not they compiled
+        hash: u64,
+        /// When the file was last read
+        checked: Instant
+    }
+}
 
 pub struct Shader {
-    pipeline: wgpu::RenderPipeline
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    descriptor: PipelineDescriptor,
+    name: String,
+    source: Source
 }
 
 impl Shader {
-    pub fn from_source(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, layouts: &[&wgpu::BindGroupLayout], name: &str, source: &str) -> Shader {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some(name),
-            source: wgpu::ShaderSource::Wgsl(Cow::from(source))
-        });
+    /// Builds a shader from the WGSL file at `path`. Debug builds read the file, and [reload](Shader::reload_if_changed)
+    /// it whenever it changes; release builds use `embedded`, which should be the same file included with
+    /// `include_str!`. Debug builds also fall back to `embedded` if the file can't be loaded.
+    pub fn load(
+        device: &wgpu::Device, format: wgpu::TextureFormat, layouts: &[&wgpu::BindGroupLayout],
+        descriptor: &PipelineDescriptor, name: &str, path: &str, embedded: &str
+    ) -> Shader {
+        if !cfg!(debug_assertions) {
+            return Shader::from_source(device, format, layouts, descriptor, name, embedded);
+        }
+
+        Shader::from_file(device, format, layouts, descriptor, name, path).unwrap_or_else(|e| {
+            log::warn!("failed to load shader {} from {} ({}); using the copy built into the binary", name, path, e);
+            Shader::from_source(device, format, layouts, descriptor, name, embedded)
+        })
+    }
+
+    /// Builds a shader from WGSL source that is compiled into the binary, such as with `include_str!`
+    pub fn from_source(
+        device: &wgpu::Device, format: wgpu::TextureFormat, layouts: &[&wgpu::BindGroupLayout],
+        descriptor: &PipelineDescriptor, name: &str, source: &str
+    ) -> Shader {
+        let layout = Shader::pipeline_layout(device, layouts, name);
+        let pipeline = Shader::build_pipeline(device, &layout, format, descriptor, name, source);
+
+        Shader {
+            pipeline, layout, format,
+            descriptor: descriptor.clone(),
+            name: name.to_string(),
+            source: Source::Embedded
+        }
+    }
+
+    /// Builds a shader from a WGSL file, which is [reloaded](Shader::reload_if_changed) whenever it changes. Fails if
+    /// the file can't be read or doesn't compile.
+    pub fn from_file(
+        device: &wgpu::Device, format: wgpu::TextureFormat, layouts: &[&wgpu::BindGroupLayout],
+        descriptor: &PipelineDescriptor, name: &str, path: impl Into<PathBuf>
+    ) -> anyhow::Result<Shader> {
+        let path = path.into();
 
-        let pipeline_layout = device.create_pipeline_layout(
+        let source = std::fs::read_to_string(&path)?;
+        validate_wgsl(&source, &path)?;
+
+        let layout = Shader::pipeline_layout(device, layouts, name);
+        let pipeline = Shader::build_pipeline_checked(device, &layout, format, descriptor, name, &source)
+            .map_err(|e| Error::InvalidShader(format!("{}: {}", path.display(), e)))?;
+
+        log::info!("loaded shader {} from {}", name, path.display());
+        Ok(Shader {
+            pipeline, layout, format,
+            descriptor: descriptor.clone(),
+            name: name.to_string(),
+            source: Source::File { path, hash: hash(&source), checked: Instant::now() }
+        })
+    }
+
+    /// Rebuilds the pipeline if the shader's file has changed since it was last read, checking at most once every
+    /// [RELOAD_INTERVAL]. If the new version doesn't compile, the error is logged and the previous pipeline is kept.
+    /// Returns whether the pipeline was rebuilt.
+    pub fn reload_if_changed(&mut self, device: &wgpu::Device) -> bool {
+        match &self.source {
+            Source::File { checked, .. } if checked.elapsed() >= RELOAD_INTERVAL => self.reload(device),
+            _ => false
+        }
+    }
+
+    /// Rebuilds the pipeline if the shader's file has changed since it was last read, however recently that was.
+    /// Changes are found by comparing contents rather than modification times, which can be too coarse to tell two
+    /// quick saves apart.
+    fn reload(&mut self, device: &wgpu::Device) -> bool {
+        let Source::File { path, hash: last_hash, checked } = &mut self.source else {
+            return false;
+        };
+
+        *checked = Instant::now();
+
+        let source = match std::fs::read_to_string(&*path) {
+            Ok(source) => source,
+            Err(e) => {
+                log::error!("failed to read shader {}: {}", path.display(), e);
+                return false;
+            }
+        };
+
+        // even if the new version is broken, there's no point trying it again until it changes
+        let new_hash = hash(&source);
+        if new_hash == *last_hash {
+            return false;
+        }
+        *last_hash = new_hash;
+
+        if let Err(e) = validate_wgsl(&source, path) {
+            log::error!("{}; keeping the previous version", e);
+            return false;
+        }
+
+        match Shader::build_pipeline_checked(device, &self.layout, self.format, &self.descriptor, &self.name, &source) {
+            Ok(pipeline) => {
+                log::info!("reloaded shader {} from {}", self.name, path.display());
+                self.pipeline = pipeline;
+                true
+            },
+            Err(e) => {
+                log::error!("failed to rebuild shader {}: {}; keeping the previous version", path.display(), e);
+                false
+            }
+        }
+    }
+
+    fn pipeline_layout(device: &wgpu::Device, layouts: &[&wgpu::BindGroupLayout], name: &str) -> wgpu::PipelineLayout {
+        device.create_pipeline_layout(
             &wgpu::PipelineLayoutDescriptor {
                 label: Some(name),
                 bind_group_layouts: layouts,
                 push_constant_ranges: &[]
             }
-        );
+        )
+    }
+
+    /// Builds a pipeline, catching the validation errors that would otherwise panic. naga catches most mistakes
+    /// beforehand, but not ones that only show up against the pipeline, like mismatched bindings.
+    fn build_pipeline_checked(
+        device: &wgpu::Device, layout: &wgpu::PipelineLayout, format: wgpu::TextureFormat,
+        descriptor: &PipelineDescriptor, name: &str, source: &str
+    ) -> Result<wgpu::RenderPipeline, wgpu::Error> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = Shader::build_pipeline(device, layout, format, descriptor, name, source);
+
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(e) => Err(e),
+            None => Ok(pipeline)
+        }
+    }
+
+    fn build_pipeline(
+        device: &wgpu::Device, layout: &wgpu::PipelineLayout, format: wgpu::TextureFormat,
+        descriptor: &PipelineDescriptor, name: &str, source: &str
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(Cow::from(source))
+        });
 
-        let pipeline = device.create_render_pipeline(
+        device.create_render_pipeline(
             &wgpu::RenderPipelineDescriptor {
                 label: Some(name),
-                layout: Some(&pipeline_layout),
+                layout: Some(layout),
                 vertex: wgpu::VertexState {
                     module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[Vertex::layout()]
+                    entry_point: descriptor.vertex_entry_point,
+                    buffers: &descriptor.vertex_layouts
                 },
                 fragment: Some(wgpu::FragmentState {
                     module: &shader,
-                    entry_point: "fs_main",
+                    entry_point: descriptor.fragment_entry_point,
                     targets: &[
                         Some(wgpu::ColorTargetState {
-                            format: config.format,
-                            blend: None,
+                            format,
+                            blend: descriptor.blend,
                             write_mask: wgpu::ColorWrites::ALL
                         })
                     ]
                 }),
 
                 primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    topology: descriptor.topology,
                     strip_index_format: None,
                     front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Back),
-                    // cull_mode: None,
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    // polygon_mode: wgpu::PolygonMode::Line,
+                    cull_mode: descriptor.cull_mode,
+                    polygon_mode: descriptor.polygon_mode,
                     unclipped_depth: false,
                     conservative: false
                 },
 
-                depth_stencil: Some(wgpu::DepthStencilState {
-                    format: wgpu::TextureFormat::Depth32Float,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
+                depth_stencil: descriptor.depth.map(|depth| wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: depth.write,
+                    depth_compare: depth.compare,
                     stencil: wgpu::StencilState::default(),
                     bias: wgpu::DepthBiasState::default()
                 }),
                 multisample: wgpu::MultisampleState {
-                    count: 1,
+                    count: descriptor.sample_count,
                     mask: !0,
                     alpha_to_coverage_enabled: false
                 },
                 multiview: None
             }
-        );
-
-        Shader { pipeline }
+        )
     }
 }
 
+fn hash(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Parses and validates WGSL source, so that mistakes can be reported rather than crashing when the shader is built.
+/// Errors give the `path:line:column` of the mistake.
+fn validate_wgsl(source: &str, path: &Path) -> Result<(), Error> {
+    let error = |location: Option<naga::SourceLocation>, message: String| {
+        let (line, column) = location.map_or((0, 0), |location| (location.line_number, location.line_position));
+        Error::InvalidShader(format!("{}:{}:{}: {}", path.display(), line, column, message))
+    };
+
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| error(e.location(source), e.message().to_string()))?;
+
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| error(e.location(source), e.as_inner().to_string()))?;
+
+    Ok(())
+}
+
 pub trait UseShader<'a> {
     fn use_shader(&mut self, shader: &'a Shader);
 }
@@ -83,4 +326,137 @@ where 'a: 'b {
     fn use_shader(&mut self, shader: &'a Shader) {
         self.set_pipeline(&shader.pipeline);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shaders_are_valid() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/shaders");
+
+        validate_wgsl(include_str!("../shaders/shader.wgsl"), &dir.join("shader.wgsl")).unwrap();
+        validate_wgsl(include_str!("../shaders/outline.wgsl"), &dir.join("outline.wgsl")).unwrap();
+    }
+
+    #[test]
+    fn reloads_when_file_changes() {
+        use crate::renderer::{test_renderer, GpuResource};
+        use crate::world::BlockRegistry;
+
+        let Some(renderer) = test_renderer(winit::dpi::PhysicalSize::new(16, 16), &BlockRegistry::with_default_blocks()) else {
+            return;
+        };
+
+        let source = include_str!("../shaders/shader.wgsl");
+        let path = std::env::temp_dir().join(format!("voxel-reload-test-{}.wgsl", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+
+        let layouts = [renderer.camera.bind_group_layout(), renderer.block_textures.bind_group_layout()];
+        let mut shader = Shader::from_file(
+            &renderer.device, renderer.target.format(), &layouts, &PipelineDescriptor::opaque(), "reload test", &path
+        ).unwrap();
+
+        // unchanged, then broken: the original pipeline is kept
+        assert!(!shader.reload(&renderer.device));
+
+        std::fs::write(&path, source.replacen("fn fs_main", "fn fs_main(", 1)).unwrap();
+        assert!(!shader.reload(&renderer.device));
+
+        // the same length as the original, so only the contents tell it apart
+        std::fs::write(&path, source.replacen("colour.a < 0.5", "colour.a < 0.4", 1)).unwrap();
+        assert!(shader.reload(&renderer.device));
+        assert!(!shader.reload(&renderer.device));
+
+        // checked too soon after the last reload to notice the change
+        std::fs::write(&path, source).unwrap();
+        assert!(!shader.reload_if_changed(&renderer.device));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unloadable_files_fall_back_to_embedded() {
+        use crate::renderer::{test_renderer, GpuResource};
+        use crate::world::BlockRegistry;
+
+        let Some(renderer) = test_renderer(winit::dpi::PhysicalSize::new(16, 16), &BlockRegistry::with_default_blocks()) else {
+            return;
+        };
+
+        let layouts = [renderer.camera.bind_group_layout(), renderer.block_textures.bind_group_layout()];
+        let shader = Shader::load(
+            &renderer.device, renderer.target.format(), &layouts, &PipelineDescriptor::opaque(), "fallback test",
+            "does/not/exist.wgsl", include_str!("../shaders/shader.wgsl")
+        );
+
+        assert!(matches!(shader.source, Source::Embedded));
+
+        // valid WGSL, but binding a group the pipeline layout doesn't have
+        let path = std::env::temp_dir().join(format!("voxel-mismatched-test-{}.wgsl", std::process::id()));
+        std::fs::write(&path, "
+            @group(2) @binding(0) var<uniform> tint: vec4<f32>;
+
+            @vertex
+            fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+                return vec4<f32>(position, 1.0);
+            }
+
+            @fragment
+            fn fs_main() -> @location(0) vec4<f32> {
+                return tint;
+            }
+        ").unwrap();
+
+        let shader = Shader::load(
+            &renderer.device, renderer.target.format(), &layouts, &PipelineDescriptor::opaque(), "fallback test",
+            path.to_str().unwrap(), include_str!("../shaders/shader.wgsl")
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(shader.source, Source::Embedded));
+    }
+
+    #[test]
+    fn presets_build() {
+        use crate::renderer::{test_renderer, GpuResource};
+        use crate::world::BlockRegistry;
+
+        let Some(renderer) = test_renderer(winit::dpi::PhysicalSize::new(16, 16), &BlockRegistry::with_default_blocks()) else {
+            return;
+        };
+
+        let device = &renderer.device;
+        let layouts = [renderer.camera.bind_group_layout(), renderer.block_textures.bind_group_layout()];
+        let block_shader = include_str!("../shaders/shader.wgsl");
+
+        let mut presets = vec![
+            ("opaque", PipelineDescriptor::opaque()),
+            ("transparent", PipelineDescriptor::transparent())
+        ];
+        if device.features().contains(wgpu::Features::POLYGON_MODE_LINE) {
+            presets.push(("wireframe", PipelineDescriptor::wireframe()));
+        }
+
+        for (name, descriptor) in presets {
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+            Shader::from_source(device, renderer.target.format(), &layouts, &descriptor, name, block_shader);
+
+            if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+                panic!("failed to build {} pipeline: {}", name, e);
+            }
+        }
+    }
+
+    #[test]
+    fn errors_give_line_and_column() {
+        let source = "@fragment\nfn fs_main() -> @location(0) vec4<f32> {\n    return vec4<f32>(1.0) +;\n}\n";
+        let error = validate_wgsl(source, Path::new("broken.wgsl")).unwrap_err().to_string();
+        assert!(error.starts_with("invalid shader at broken.wgsl:3:"), "{}", error);
+
+        let source = "@fragment\nfn fs_main() -> @location(0) vec4<f32> {\n    return vec4<f32>(1.0, 2.0);\n}\n";
+        let error = validate_wgsl(source, Path::new("broken.wgsl")).unwrap_err().to_string();
+        assert!(error.starts_with("invalid shader at broken.wgsl:3:"), "{}", error);
+    }
+}
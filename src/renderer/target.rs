@@ -0,0 +1,410 @@
+use std::sync::mpsc;
+
+use winit::{window::Window, dpi::PhysicalSize};
+
+use image::RgbaImage;
+
+use super::Error;
+
+/// The format of offscreen render targets, which can be read back without any conversion
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Where a [Renderer](super::Renderer) draws each frame
+pub enum RenderTarget {
+    /// A window's surface, presented to the screen
+    Window {
+        window: Window,
+        surface: wgpu::Surface,
+        config: wgpu::SurfaceConfiguration
+    },
+    /// A texture that can be read back after rendering, for drawing without a display
+    Offscreen {
+        texture: wgpu::Texture
+    }
+}
+
+/// The texture being drawn into for one frame
+pub struct Frame {
+    pub view: wgpu::TextureView,
+    /// Present when drawing to a window, and shown once the frame is [presented](Frame::present)
+    surface_texture: Option<wgpu::SurfaceTexture>
+}
+
+impl Frame {
+    /// The texture being drawn into; `target` must be the target this frame came from
+    pub fn texture<'a>(&'a self, target: &'a RenderTarget) -> &'a wgpu::Texture {
+        match (&self.surface_texture, target) {
+            (Some(surface_texture), _) => &surface_texture.texture,
+            (None, RenderTarget::Offscreen { texture }) => texture,
+            (None, RenderTarget::Window { .. }) => panic!("frame was not drawn to this target")
+        }
+    }
+
+    pub fn present(self) {
+        if let Some(texture) = self.surface_texture {
+            texture.present();
+        }
+    }
+}
+
+impl RenderTarget {
+    pub fn offscreen(device: &wgpu::Device, size: PhysicalSize<u32>) -> RenderTarget {
+        RenderTarget::Offscreen { texture: offscreen_texture(device, size, OFFSCREEN_FORMAT) }
+    }
+
+    pub fn window(&self) -> Option<&Window> {
+        match self {
+            RenderTarget::Window { window, .. } => Some(window),
+            RenderTarget::Offscreen { .. } => None
+        }
+    }
+
+    /// Whether each [Frame]'s texture can be [read back](Readback) before it is presented
+    pub fn can_copy_frames(&self) -> bool {
+        match self {
+            RenderTarget::Window { config, .. } => config.usage.contains(wgpu::TextureUsages::COPY_SRC),
+            RenderTarget::Offscreen { .. } => true
+        }
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        match self {
+            RenderTarget::Window { config, .. } => config.format,
+            RenderTarget::Offscreen { texture } => texture.format()
+        }
+    }
+
+    /// Changes the size of the target. Offscreen targets are replaced, losing their contents.
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        match self {
+            RenderTarget::Window { surface, config, .. } => {
+                config.width = size.width;
+                config.height = size.height;
+                surface.configure(device, config);
+            },
+            RenderTarget::Offscreen { texture } => *texture = offscreen_texture(device, size, OFFSCREEN_FORMAT)
+        }
+    }
+
+    /// Gets the texture to draw the next frame into
+    pub fn frame(&self) -> Result<Frame, wgpu::SurfaceError> {
+        match self {
+            RenderTarget::Window { surface, .. } => {
+                let surface_texture = surface.get_current_texture()?;
+                let view = surface_texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+                Ok(Frame { view, surface_texture: Some(surface_texture) })
+            },
+            RenderTarget::Offscreen { texture } => Ok(Frame {
+                view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                surface_texture: None
+            })
+        }
+    }
+}
+
+/// Whether `surface` can be configured with [COPY_SRC](wgpu::TextureUsages::COPY_SRC) usage on `adapter`. wgpu 0.16
+/// validates the usage against the backend's capabilities but doesn't expose them, and configuring a surface with an
+/// unsupported usage panics, so this asks the backend itself.
+pub fn surface_supports_copy(adapter: &wgpu::Adapter, surface: &mut wgpu::Surface) -> bool {
+    match adapter.get_info().backend {
+        #[cfg(any(windows, all(unix, not(target_os = "macos"), not(target_os = "ios"))))]
+        wgpu::Backend::Vulkan => {
+            use wgpu_hal::{Adapter, api::Vulkan};
+
+            // safe as the raw adapter and surface are only used to query capabilities, and don't outlive the call
+            let supported = unsafe {
+                adapter.as_hal::<Vulkan, _, _>(|adapter| {
+                    surface.as_hal_mut::<Vulkan, _, _>(|surface| {
+                        let capabilities = adapter?.surface_capabilities(surface?)?;
+                        Some(capabilities.usage.contains(wgpu_hal::TextureUses::COPY_SRC))
+                    })
+                })
+            };
+
+            supported.unwrap_or(false)
+        },
+        // always supported, whatever the driver
+        wgpu::Backend::Dx12 => true,
+        // Metal and GL surfaces can only be drawn into
+        _ => false
+    }
+}
+
+/// Creates a texture that can be drawn into and then [read back](Readback). Window surfaces can't always be copied
+/// from, so anything that needs reading back is drawn into one of these instead.
+pub fn offscreen_texture(device: &wgpu::Device, size: PhysicalSize<u32>, format: wgpu::TextureFormat) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("offscreen target"),
+        size: wgpu::Extent3d { width: size.width, height: size.height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[]
+    })
+}
+
+/// Copies a 2D texture back from the GPU into an image, waiting for the copy to finish
+pub fn read_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> anyhow::Result<RgbaImage> {
+    Readback::start(device, queue, texture)?.wait(device)
+}
+
+/// A copy of a texture on its way back from the GPU, which can either be waited on or checked each frame
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    /// The length of each row in the buffer, including padding
+    padded_row_bytes: u32,
+    bgra: bool,
+    /// Receives the result of mapping the buffer, once the copy has finished
+    receiver: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>
+}
+
+impl Readback {
+    /// Starts copying a 2D texture into a buffer that can be read from the CPU. The copy happens after anything
+    /// already submitted to `queue`, so a frame can be read back once it has been drawn. The texture must have been
+    /// created with [COPY_SRC](wgpu::TextureUsages::COPY_SRC) usage, and have an 8-bit RGBA or BGRA format.
+    pub fn start(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Result<Readback, Error> {
+        let format = texture.format();
+        let bgra = match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => return Err(Error::UnsupportedReadbackFormat(format))
+        };
+
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            return Err(Error::NotCopySource);
+        }
+
+        let (width, height) = (texture.width(), texture.height());
+
+        // rows of a texture copy have to be padded out to a multiple of the alignment
+        let row_bytes = width * 4;
+        let padded_row_bytes = row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback buffer"),
+            size: (padded_row_bytes * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder")
+        });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height)
+                }
+            },
+            texture.size()
+        );
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            // the receiver only goes away if the readback was dropped before finishing
+            let _ = sender.send(result);
+        });
+
+        Ok(Readback { buffer, width, height, padded_row_bytes, bgra, receiver })
+    }
+
+    /// Blocks until the copy has finished, and returns the image
+    pub fn wait(self, device: &wgpu::Device) -> anyhow::Result<RgbaImage> {
+        device.poll(wgpu::Maintain::Wait);
+        self.receiver.recv()??;
+
+        Ok(self.read())
+    }
+
+    /// Returns the image if the copy has finished, or `None` if it is still in progress. The device has to be
+    /// [polled](wgpu::Device::poll) for the copy to make progress.
+    pub fn try_finish(&self) -> Option<anyhow::Result<RgbaImage>> {
+        match self.receiver.try_recv() {
+            Ok(Ok(())) => Some(Ok(self.read())),
+            Ok(Err(e)) => Some(Err(e.into())),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(e @ mpsc::TryRecvError::Disconnected) => Some(Err(e.into()))
+        }
+    }
+
+    /// Copies the mapped buffer into an image, dropping the row padding and converting BGRA to RGBA
+    fn read(&self) -> RgbaImage {
+        let row_bytes = (self.width * 4) as usize;
+
+        let mut pixels = Vec::with_capacity(row_bytes * self.height as usize);
+        for row in self.buffer.slice(..).get_mapped_range().chunks_exact(self.padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes]);
+        }
+        self.buffer.unmap();
+
+        if self.bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        RgbaImage::from_raw(self.width, self.height, pixels).expect("readback buffer has the wrong size")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use glam::{ivec3, vec3};
+
+    use super::*;
+    use crate::renderer::test_renderer;
+    use crate::world::{World, BlockRegistry, Chunk, blocks};
+
+    /// The largest difference in any channel for two pixels to count as the same
+    const CHANNEL_TOLERANCE: u8 = 8;
+    /// The fraction of pixels that can differ before an image no longer matches its reference
+    const MISMATCH_TOLERANCE: f32 = 0.01;
+
+    fn golden_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/res/golden").join(name).with_extension("png")
+    }
+
+    /// The fraction of pixels that differ between two images of the same size
+    fn mismatch(a: &RgbaImage, b: &RgbaImage) -> f32 {
+        let differing = a.pixels()
+            .zip(b.pixels())
+            .filter(|(a, b)| a.0.iter().zip(b.0).any(|(&a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE))
+            .count();
+
+        differing as f32 / (a.width() * a.height()) as f32
+    }
+
+    /// Compares an image against the reference with the given name. Setting `VOXEL_BLESS` saves the image as the new
+    /// reference instead.
+    fn assert_matches_golden(image: &RgbaImage, name: &str) {
+        let path = golden_path(name);
+
+        if std::env::var_os("VOXEL_BLESS").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image.save(&path).unwrap();
+            eprintln!("saved new reference image {}", path.display());
+            return;
+        }
+
+        assert!(path.exists(), "missing reference {}, rerun with VOXEL_BLESS=1", path.display());
+
+        let reference = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), reference.dimensions(), "image is a different size to {}", path.display());
+
+        let mismatch = mismatch(image, &reference);
+        if mismatch > MISMATCH_TOLERANCE {
+            let actual = path.with_extension("actual.png");
+            image.save(&actual).unwrap();
+
+            panic!("{:.1}% of pixels differ from {}; saved the render as {}", mismatch * 100.0, path.display(), actual.display());
+        }
+    }
+
+    /// A grass floor with a few blocks on it: a stone pillar, a glass block, and a lamp under a roof
+    fn test_world() -> World {
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+
+        for x in -1..=0 {
+            for z in -1..=0 {
+                world.insert_chunk(ivec3(x, 0, z), Chunk::new());
+            }
+        }
+
+        for x in -16..16 {
+            for z in -16..16 {
+                world.set_block(ivec3(x, 0, z), blocks::GRASS);
+            }
+        }
+
+        for y in 1..=4 {
+            world.set_block(ivec3(-3, y, -6), blocks::STONE);
+        }
+        world.set_block(ivec3(1, 1, -4), blocks::GLASS);
+
+        for x in 3..=6 {
+            for z in -8..=-5 {
+                world.set_block(ivec3(x, 3, z), blocks::STONE);
+            }
+        }
+        world.set_block(ivec3(4, 1, -6), blocks::LAMP);
+
+        world
+    }
+
+    #[test]
+    fn renders_golden_scene() {
+        let world = test_world();
+
+        let Some(mut renderer) = test_renderer(PhysicalSize::new(160, 120), world.registry()) else {
+            return;
+        };
+
+        renderer.camera.position = vec3(0.0, 4.0, 4.0);
+        renderer.camera.yaw = -90.0;
+        renderer.camera.pitch = -25.0;
+
+        for pos in world.chunk_positions() {
+            renderer.update_chunk(&world, pos);
+        }
+        renderer.finish_meshing();
+
+        renderer.render().unwrap();
+        let image = renderer.read_pixels().unwrap();
+
+        assert_matches_golden(&image, "scene");
+    }
+
+    #[test]
+    fn screenshots_match_read_pixels() {
+        let world = test_world();
+
+        let Some(mut renderer) = test_renderer(PhysicalSize::new(64, 48), world.registry()) else {
+            return;
+        };
+
+        for pos in world.chunk_positions() {
+            renderer.update_chunk(&world, pos);
+        }
+        renderer.finish_meshing();
+
+        renderer.request_screenshot();
+        renderer.render().unwrap();
+
+        let start = std::time::Instant::now();
+        let screenshot = loop {
+            if let Some(result) = renderer.take_screenshots().pop() {
+                break result.unwrap();
+            }
+
+            assert!(start.elapsed() < std::time::Duration::from_secs(10), "screenshot was never read back");
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+
+        assert_eq!(screenshot, renderer.read_pixels().unwrap());
+    }
+
+    #[test]
+    fn mismatch_counts_pixels_outside_tolerance() {
+        let a = RgbaImage::from_pixel(10, 10, image::Rgba([100, 100, 100, 255]));
+        let mut b = a.clone();
+
+        b.put_pixel(0, 0, image::Rgba([100 + CHANNEL_TOLERANCE, 100, 100, 255]));
+        b.put_pixel(1, 0, image::Rgba([100, 100, 101 + CHANNEL_TOLERANCE, 255]));
+
+        assert_eq!(mismatch(&a, &b), 0.01);
+    }
+}
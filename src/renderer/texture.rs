@@ -1,4 +1,66 @@
-use image::GenericImageView;
+use std::path::Path;
+
+use image::{RgbaImage, Rgba32FImage};
+
+/// The size of the placeholder used for textures that fail to load
+const MISSING_TEXTURE_SIZE: u32 = 16;
+
+/// Texels with less alpha than this are discarded by the block shader
+const ALPHA_CUTOFF: f32 = 0.5;
+
+/// How a texture is filtered when sampled
+#[derive(Copy, Clone, Debug)]
+pub struct SamplerSettings {
+    /// Used when a texel covers more than one pixel
+    pub mag_filter: wgpu::FilterMode,
+    /// Used when a texel covers less than one pixel
+    pub min_filter: wgpu::FilterMode,
+    /// Used to blend between mip levels
+    pub mipmap_filter: wgpu::FilterMode,
+    /// The maximum anisotropy, from 1 (off) to 16. Only takes effect when every filter is linear.
+    pub anisotropy: u16
+}
+
+impl Default for SamplerSettings {
+    /// Blocky up close, and smooth into the distance
+    fn default() -> SamplerSettings {
+        SamplerSettings {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy: 1
+        }
+    }
+}
+
+impl SamplerSettings {
+    /// Linear filtering everywhere, with up to `anisotropy` anisotropic filtering
+    pub fn anisotropic(anisotropy: u16) -> SamplerSettings {
+        SamplerSettings {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy
+        }
+    }
+
+    /// The anisotropy that can actually be used; wgpu only allows it with linear filtering, and in powers of two
+    fn anisotropy_clamp(&self) -> u16 {
+        let linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|&filter| filter == wgpu::FilterMode::Linear);
+
+        if !linear && self.anisotropy > 1 {
+            log::warn!("ignoring anisotropy of {}, as it needs linear filtering", self.anisotropy);
+            return 1;
+        }
+
+        match self.anisotropy.clamp(1, 16) {
+            a if a.is_power_of_two() => a,
+            a => a.next_power_of_two() / 2
+        }
+    }
+}
 
 pub struct Texture {
     // texture: wgpu::Texture,
@@ -9,23 +71,34 @@ pub struct Texture {
 }
 
 impl Texture {
+    /// The format of [depth buffers](Texture::new_depth_buffer), which pipelines that test depth have to match
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
     // pub fn texture(&self) -> &wgpu::Texture { &self.texture }
     pub fn view(&self) -> &wgpu::TextureView { &self.view }
     // pub fn sampler(&self) -> &wgpu::Sampler { &self.sampler }
 
-    pub fn from_image(device: &wgpu::Device, queue: &wgpu::Queue, image: &image::DynamicImage, label: &str) -> Texture {
-        let rgba = image.to_rgba8();
-        let (width, height) = image.dimensions();
+    /// Creates a 2D texture array with one layer for each image, with a full chain of mipmaps. Every image must be the
+    /// same size.
+    ///
+    /// Each layer's mipmaps are generated from that layer alone, so textures never bleed into each other.
+    pub fn from_layers(
+        device: &wgpu::Device, queue: &wgpu::Queue, layers: &[image::RgbaImage], sampler: &SamplerSettings, label: &str
+    ) -> Texture {
+        let (width, height) = layers[0].dimensions();
+        assert!(layers.iter().all(|layer| layer.dimensions() == (width, height)), "texture layers differ in size");
 
         let size = wgpu::Extent3d {
             width, height,
-            depth_or_array_layers: 1
+            depth_or_array_layers: layers.len() as u32
         };
+        let mip_level_count = size.max_mips(wgpu::TextureDimension::D2);
+
         let texture = device.create_texture(
             &wgpu::TextureDescriptor {
                 label: Some(label),
                 size,
-                mip_level_count: 1,
+                mip_level_count,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
                 format: wgpu::TextureFormat::Rgba8UnormSrgb,
@@ -34,32 +107,43 @@ impl Texture {
             }
         );
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * width),
-                rows_per_image: Some(height)
-            },
-            size
-        );
+        for (layer, image) in layers.iter().enumerate() {
+            for (level, mip) in generate_mips(image).iter().enumerate() {
+                let (width, height) = mip.dimensions();
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level: level as u32,
+                        origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 }
+                    },
+                    mip,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(4 * width),
+                        rows_per_image: Some(height)
+                    },
+                    wgpu::Extent3d { width, height, depth_or_array_layers: 1 }
+                );
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(
             &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                // repeat, so that a texture can tile across a merged face
+                address_mode_u: wgpu::AddressMode::Repeat,
+                address_mode_v: wgpu::AddressMode::Repeat,
+                address_mode_w: wgpu::AddressMode::Repeat,
                 label: Some(label),
-                mag_filter: wgpu::FilterMode::Nearest,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
+                mag_filter: sampler.mag_filter,
+                min_filter: sampler.min_filter,
+                mipmap_filter: sampler.mipmap_filter,
+                anisotropy_clamp: sampler.anisotropy_clamp(),
                 ..Default::default()
             }
         );
@@ -73,7 +157,7 @@ impl Texture {
                         visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Texture { 
                             sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
                             multisampled: false
                         },
                         count: None
@@ -108,10 +192,10 @@ impl Texture {
         Texture { view, bind_group_layout, bind_group }
     }
 
-    pub fn new_depth_buffer(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Texture {
+    pub fn new_depth_buffer(device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) -> Texture {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width: size.width,
+            height: size.height,
             depth_or_array_layers: 1
         };
 
@@ -122,7 +206,7 @@ impl Texture {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Depth32Float,
+                format: Texture::DEPTH_FORMAT,
                 usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
                 view_formats: &[]
             }
@@ -201,4 +285,206 @@ impl crate::renderer::GpuResource for Texture {
     fn update(&self, _queue: &wgpu::Queue) {
         unimplemented!()
     }
-}
\ No newline at end of file
+}
+
+/// Loads `<name>.png` from `dir`
+pub fn load_png(dir: &Path, name: &str) -> anyhow::Result<RgbaImage> {
+    let path = dir.join(name).with_extension("png");
+
+    let image = image::open(&path)
+        .map_err(|e| anyhow::anyhow!("failed to open {}: {}", path.display(), e))?;
+
+    Ok(image.to_rgba8())
+}
+
+/// Loads each named texture with `load`, to be used as the layers of a texture array. Textures that can't be loaded
+/// are replaced with a placeholder, and any that differ in size from the first are resized to match it.
+pub fn load_layers(names: &[String], load: impl Fn(&str) -> anyhow::Result<RgbaImage>) -> Vec<RgbaImage> {
+    let mut layers: Vec<RgbaImage> = Vec::with_capacity(names.len());
+
+    for name in names {
+        let image = match load(name) {
+            Ok(image) => image,
+            Err(e) => {
+                log::warn!("failed to load texture '{}': {}", name, e);
+                missing_texture()
+            }
+        };
+
+        let image = match layers.first() {
+            Some(first) if first.dimensions() != image.dimensions() => {
+                let (width, height) = first.dimensions();
+                log::warn!("resizing texture '{}' to {}x{} to match the other textures", name, width, height);
+
+                image::imageops::resize(&image, width, height, image::imageops::FilterType::Nearest)
+            },
+            _ => image
+        };
+
+        layers.push(image);
+    }
+
+    if layers.is_empty() {
+        layers.push(missing_texture());
+    }
+
+    layers
+}
+
+/// Downsamples an sRGB image into a full mip chain, halving it each level until it is 1x1. The first level is the image
+/// itself.
+///
+/// Colours are averaged in linear space, weighted by alpha so that transparent texels don't bleed into their
+/// neighbours. Averaging alone would fade cutout textures like leaves away into the distance, so each level's alpha is
+/// then scaled to keep the same fraction of texels above [ALPHA_CUTOFF] as the full image.
+pub fn generate_mips(image: &RgbaImage) -> Vec<RgbaImage> {
+    let linear = Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0.map(|c| c as f32 / 255.0);
+        image::Rgba([srgb_to_linear(r) * a, srgb_to_linear(g) * a, srgb_to_linear(b) * a, a])
+    });
+    let coverage = alpha_coverage(&linear, 1.0);
+
+    let mut mips = vec![image.clone()];
+
+    loop {
+        let (width, height) = mips.last().unwrap().dimensions();
+        if width == 1 && height == 1 {
+            break;
+        }
+
+        // always shrink from the full image, so that errors don't build up level by level
+        let (width, height) = ((width / 2).max(1), (height / 2).max(1));
+        let mip = image::imageops::resize(&linear, width, height, image::imageops::FilterType::Triangle);
+        let scale = coverage_scale(&mip, coverage);
+
+        mips.push(RgbaImage::from_fn(width, height, |x, y| {
+            let [r, g, b, a] = mip.get_pixel(x, y).0;
+            let unpremultiply = |c: f32| if a > 0.0 { linear_to_srgb(c / a) } else { 0.0 };
+
+            let pixel = [unpremultiply(r), unpremultiply(g), unpremultiply(b), (a * scale).min(1.0)];
+            image::Rgba(pixel.map(|c| (c * 255.0).round() as u8))
+        }));
+    }
+
+    mips
+}
+
+/// The fraction of texels that would pass the alpha test if their alpha was multiplied by `scale`
+fn alpha_coverage(image: &Rgba32FImage, scale: f32) -> f32 {
+    let passing = image.pixels().filter(|pixel| pixel[3] * scale >= ALPHA_CUTOFF).count();
+    passing as f32 / (image.width() * image.height()) as f32
+}
+
+/// Finds the factor to multiply a mip level's alpha by, so that as close as possible to `coverage` of its texels pass
+/// the alpha test
+fn coverage_scale(mip: &Rgba32FImage, coverage: f32) -> f32 {
+    if alpha_coverage(mip, 1.0) == coverage {
+        return 1.0;
+    }
+
+    // coverage only grows with the scale, so binary search for it
+    let (mut low, mut high) = (0.0, 1.0 / ALPHA_CUTOFF);
+    let mut best = (1.0, (alpha_coverage(mip, 1.0) - coverage).abs());
+
+    for _ in 0..16 {
+        let scale = (low + high) / 2.0;
+        let scaled = alpha_coverage(mip, scale);
+
+        if (scaled - coverage).abs() < best.1 {
+            best = (scale, (scaled - coverage).abs());
+        }
+
+        if scaled < coverage {
+            low = scale;
+        } else {
+            high = scale;
+        }
+    }
+
+    best.0
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// A magenta and black checkerboard, which is hard to miss
+fn missing_texture() -> RgbaImage {
+    RgbaImage::from_fn(MISSING_TEXTURE_SIZE, MISSING_TEXTURE_SIZE, |x, y| {
+        if (x / 4 + y / 4) % 2 == 0 {
+            image::Rgba([255, 0, 255, 255])
+        } else {
+            image::Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_and_mismatched_layers() {
+        let dir = std::env::temp_dir().join(format!("engine-textures-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        RgbaImage::from_pixel(8, 8, image::Rgba([1, 2, 3, 255])).save(dir.join("small.png")).unwrap();
+        RgbaImage::from_pixel(32, 32, image::Rgba([4, 5, 6, 255])).save(dir.join("large.png")).unwrap();
+
+        let names = ["small", "missing", "large"].map(String::from);
+        let layers = load_layers(&names, |name| load_png(&dir, name));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(layers.len(), 3);
+        assert!(layers.iter().all(|layer| layer.dimensions() == (8, 8)));
+        assert_eq!(layers[0].get_pixel(0, 0), &image::Rgba([1, 2, 3, 255]));
+        assert_eq!(layers[2].get_pixel(7, 7), &image::Rgba([4, 5, 6, 255]));
+    }
+
+    #[test]
+    fn mip_chain() {
+        let image = RgbaImage::from_pixel(16, 4, image::Rgba([200, 100, 50, 255]));
+        let sizes: Vec<_> = generate_mips(&image).iter().map(|mip| mip.dimensions()).collect();
+
+        assert_eq!(sizes, [(16, 4), (8, 2), (4, 1), (2, 1), (1, 1)]);
+        assert_eq!(generate_mips(&image).last().unwrap().get_pixel(0, 0), &image::Rgba([200, 100, 50, 255]));
+    }
+
+    #[test]
+    fn mips_average_in_linear_space() {
+        let image = RgbaImage::from_fn(2, 2, |x, _| image::Rgba(if x == 0 { [0, 0, 0, 255] } else { [255, 255, 255, 255] }));
+
+        // halfway between black and white in linear light, rather than the 128 of averaging the sRGB values
+        assert_eq!(generate_mips(&image)[1].get_pixel(0, 0), &image::Rgba([188, 188, 188, 255]));
+    }
+
+    #[test]
+    fn mips_keep_alpha_coverage() {
+        // opaque red on every fourth column, with invisible blue between them
+        let image = RgbaImage::from_fn(16, 16, |x, _| image::Rgba(if x % 4 == 0 { [255, 0, 0, 255] } else { [0, 0, 255, 0] }));
+        let mips = generate_mips(&image);
+
+        let coverage = |mip: &RgbaImage| {
+            mip.pixels().filter(|pixel| pixel[3] as f32 / 255.0 >= ALPHA_CUTOFF).count() as f32 / mip.pixels().len() as f32
+        };
+
+        // plain averaging would leave every texel of the second level below the cutoff
+        assert!((coverage(&mips[1]) - 0.25).abs() <= 0.25 && coverage(&mips[1]) > 0.0);
+
+        // and the transparent texels' colour doesn't bleed into what's left
+        assert!(mips[1..].iter().flat_map(|mip| mip.pixels()).all(|pixel| pixel[2] == 0));
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::RgbaImage;
+
+/// Saves a screenshot into `dir` as a PNG named after the current time, creating the directory if needed. Encoding
+/// happens on a background thread, so that the frame isn't held up.
+pub fn save(image: RgbaImage, dir: &Path) {
+    let path = dir.join(file_name(SystemTime::now()));
+
+    std::thread::spawn(move || {
+        let result = std::fs::create_dir_all(path.parent().unwrap())
+            .map_err(image::ImageError::from)
+            .and_then(|_| image.save(&path));
+
+        match result {
+            Ok(()) => log::info!("saved screenshot to {}", path.display()),
+            Err(e) => log::error!("failed to save screenshot to {}: {}", path.display(), e)
+        }
+    });
+}
+
+/// The name of a screenshot taken at `time`, as `YYYY-MM-DD_HH-MM-SS.mmm.png` in UTC. Names sort in the order the
+/// screenshots were taken.
+fn file_name(time: SystemTime) -> PathBuf {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    let seconds = since_epoch.as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    let (year, month, day) = civil_date(days as i64);
+
+    PathBuf::from(format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{:03}.png",
+        year, month, day,
+        seconds / 3600, seconds / 60 % 60, seconds % 60,
+        since_epoch.subsec_millis()
+    ))
+}
+
+/// Converts a number of days since 1970-01-01 into a (year, month, day) date in the proleptic Gregorian calendar
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    // counts from 0000-03-01, so that leap days fall at the end of each year, in 400 year eras
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+
+    // months starting from March
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(11016), (2000, 2, 29));
+        assert_eq!(civil_date(11017), (2000, 3, 1));
+        assert_eq!(civil_date(19782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn file_names() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_164_805_042);
+        assert_eq!(file_name(time), PathBuf::from("2024-02-29_00-00-05.042.png"));
+    }
+}
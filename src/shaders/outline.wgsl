@@ -0,0 +1,22 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct OutlineUniform {
+    // xyz is the centre of the outlined block; w is unused padding
+    position: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> outline: OutlineUniform;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * vec4<f32>(position + outline.position.xyz, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}
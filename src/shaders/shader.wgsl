@@ -6,12 +6,18 @@ var<uniform> camera: CameraUniform;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) uv: vec2<f32>
+    @location(1) uv: vec2<f32>,
+    @location(2) texture: u32,
+    @location(3) occlusion: f32,
+    @location(4) light: vec2<f32>
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) uv: vec2<f32>
+    @location(0) uv: vec2<f32>,
+    @location(1) @interpolate(flat) texture: u32,
+    @location(2) occlusion: f32,
+    @location(3) light: vec2<f32>
 };
 
 @vertex
@@ -22,16 +28,35 @@ fn vs_main(
 
     out.clip_position = camera.view_proj * vec4<f32>(in.position, 1.0);
     out.uv = in.uv;
+    out.texture = in.texture;
+    out.occlusion = in.occlusion;
+    out.light = in.light;
 
     return out;
 }
 
 @group(1) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_blocks: texture_2d_array<f32>;
 @group(1) @binding(1)
-var s_diffuse: sampler;
+var s_blocks: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.uv);
-}
\ No newline at end of file
+    let colour = textureSample(t_blocks, s_blocks, in.uv, in.texture);
+
+    // mostly see-through pixels, such as the middle of glass, are cut out
+    if colour.a < 0.5 {
+        discard;
+    }
+
+    // fully occluded corners keep some light, so that they don't turn black
+    let occlusion = mix(0.4, 1.0, in.occlusion);
+
+    // each light level is 80% as bright as the one above it, over a little ambient light so caves aren't pitch black
+    let level = max(in.light.x, in.light.y) * 15.0;
+    let brightness = mix(0.03, 1.0, pow(0.8, 15.0 - level));
+
+    let shade = occlusion * brightness;
+
+    return vec4<f32>(colour.rgb * shade, colour.a);
+}
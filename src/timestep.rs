@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+/// The simulation tick rate used when `VOXEL_TICK_RATE` is not set
+const DEFAULT_TICK_RATE: u32 = 60;
+
+/// The most time the simulation will try to catch up on in one frame. Anything beyond this, such as from the window
+/// being dragged, is dropped rather than simulated all at once.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Runs a simulation at a fixed rate, independent of how often frames are rendered
+pub struct FixedTimestep {
+    tick: Duration,
+    /// Real time that has passed but not yet been simulated
+    accumulator: Duration
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: u32) -> FixedTimestep {
+        FixedTimestep { tick: Duration::from_secs(1) / tick_rate.max(1), accumulator: Duration::ZERO }
+    }
+
+    /// Uses the tick rate from the `VOXEL_TICK_RATE` environment variable, if it is set to a valid rate
+    pub fn from_env() -> FixedTimestep {
+        let tick_rate = match std::env::var("VOXEL_TICK_RATE") {
+            Ok(rate) => rate.parse().ok().filter(|&rate| rate > 0).unwrap_or_else(|| {
+                log::warn!("ignoring invalid VOXEL_TICK_RATE '{}'", rate);
+                DEFAULT_TICK_RATE
+            }),
+            Err(_) => DEFAULT_TICK_RATE
+        };
+
+        log::info!("simulating at {} ticks per second", tick_rate);
+        FixedTimestep::new(tick_rate)
+    }
+
+    /// The length of one tick, in seconds
+    pub fn dt(&self) -> f32 {
+        self.tick.as_secs_f32()
+    }
+
+    /// Adds the real time that has passed since the last frame, returning how many ticks should be run
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed.min(MAX_FRAME_TIME);
+
+        let mut ticks = 0;
+        while self.accumulator >= self.tick {
+            self.accumulator -= self.tick;
+            ticks += 1;
+        }
+
+        ticks
+    }
+
+    /// How far through the next tick the current frame is, from 0 to 1, for interpolating between the last two ticks
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.tick.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulates_partial_ticks() {
+        let mut timestep = FixedTimestep::new(20);
+
+        assert_eq!(timestep.advance(Duration::from_millis(30)), 0);
+        assert!((timestep.alpha() - 0.6).abs() < 1e-4);
+
+        assert_eq!(timestep.advance(Duration::from_millis(80)), 2);
+        assert!((timestep.alpha() - 0.2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn caps_long_frames() {
+        let mut timestep = FixedTimestep::new(60);
+
+        assert_eq!(timestep.advance(Duration::from_secs(10)), 15);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use crate::renderer::Face;
+
+/// Identifies a type of block, as registered in a [BlockRegistry].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct BlockId(pub u16);
+
+impl BlockId {
+    pub const AIR: BlockId = BlockId(0);
+
+    pub fn is_air(self) -> bool {
+        self == BlockId::AIR
+    }
+}
+
+/// The blocks registered by [BlockRegistry::with_default_blocks], in registration order.
+pub mod blocks {
+    use super::BlockId;
+
+    pub const STONE: BlockId = BlockId(1);
+    pub const DIRT: BlockId = BlockId(2);
+    pub const GRASS: BlockId = BlockId(3);
+    pub const GLASS: BlockId = BlockId(4);
+    pub const LAMP: BlockId = BlockId(5);
+    pub const SAND: BlockId = BlockId(6);
+    pub const WATER: BlockId = BlockId(7);
+}
+
+/// Refers to a texture that a block face is drawn with, as named in a [BlockRegistry]. Doubles as the texture's layer
+/// in the renderer's block texture array.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct TextureId(pub u32);
+
+/// The texture used by each face of a block.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FaceTextures {
+    pub up: TextureId,
+    pub down: TextureId,
+    pub north: TextureId,
+    pub south: TextureId,
+    pub east: TextureId,
+    pub west: TextureId
+}
+
+impl FaceTextures {
+    /// Use the same texture for every face
+    pub fn all(texture: TextureId) -> FaceTextures {
+        FaceTextures::top_bottom_sides(texture, texture, texture)
+    }
+
+    /// Use one texture for the top, one for the bottom, and one for the four sides
+    pub fn top_bottom_sides(top: TextureId, bottom: TextureId, sides: TextureId) -> FaceTextures {
+        FaceTextures { up: top, down: bottom, north: sides, south: sides, east: sides, west: sides }
+    }
+
+    pub fn get(&self, face: Face) -> TextureId {
+        match face {
+            Face::Up => self.up,
+            Face::Down => self.down,
+            Face::North => self.north,
+            Face::South => self.south,
+            Face::East => self.east,
+            Face::West => self.west
+        }
+    }
+}
+
+/// The properties shared by every block of a given type.
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub name: String,
+    /// Whether entities collide with this block, and whether it stops raycasts
+    pub solid: bool,
+    /// Whether this block completely hides the faces of the blocks behind it
+    pub opaque: bool,
+    pub textures: FaceTextures,
+    /// The level of light this block gives off, from 0 to 15
+    pub light_emission: u8
+}
+
+impl Block {
+    /// A solid, opaque block that gives off no light
+    pub fn new(name: &str, textures: FaceTextures) -> Block {
+        Block {
+            name: name.to_string(),
+            solid: true,
+            opaque: true,
+            textures,
+            light_emission: 0
+        }
+    }
+
+    pub fn transparent(mut self) -> Block {
+        self.opaque = false;
+        self
+    }
+
+    pub fn non_solid(mut self) -> Block {
+        self.solid = false;
+        self
+    }
+
+    pub fn emits_light(mut self, level: u8) -> Block {
+        self.light_emission = level.min(15);
+        self
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RegistryError {
+    #[error("a block named '{0}' is already registered")]
+    DuplicateName(String),
+    #[error("too many block types registered")]
+    RegistryFull
+}
+
+/// Holds the properties of every block type, indexed by [BlockId]. Air is always registered as [BlockId::AIR].
+///
+/// The registry also names the textures that blocks use, so that the renderer knows which images to load.
+pub struct BlockRegistry {
+    blocks: Vec<Block>,
+    names: HashMap<String, BlockId>,
+    /// Texture names, indexed by [TextureId]
+    textures: Vec<String>
+}
+
+impl BlockRegistry {
+    pub fn new() -> BlockRegistry {
+        let mut registry = BlockRegistry { blocks: vec![], names: HashMap::new(), textures: vec![] };
+
+        // air is never drawn, so it doesn't need a texture of its own
+        registry.register(Block::new("air", FaceTextures::all(TextureId(0))).transparent().non_solid())
+            .expect("empty registry rejected air");
+
+        registry
+    }
+
+    /// Creates a registry containing the blocks listed in [blocks]
+    pub fn with_default_blocks() -> BlockRegistry {
+        let mut registry = BlockRegistry::new();
+
+        let stone = registry.texture("stone");
+        let dirt = registry.texture("dirt");
+        let grass_top = registry.texture("grass_top");
+        let grass_side = registry.texture("grass_side");
+
+        let defaults = [
+            (blocks::STONE, Block::new("stone", FaceTextures::all(stone))),
+            (blocks::DIRT, Block::new("dirt", FaceTextures::all(dirt))),
+            (blocks::GRASS, Block::new("grass", FaceTextures::top_bottom_sides(grass_top, dirt, grass_side))),
+            (blocks::GLASS, Block::new("glass", FaceTextures::all(registry.texture("glass"))).transparent()),
+            (blocks::LAMP, Block::new("lamp", FaceTextures::all(registry.texture("lamp"))).emits_light(15)),
+            (blocks::SAND, Block::new("sand", FaceTextures::all(registry.texture("sand")))),
+            (blocks::WATER, Block::new("water", FaceTextures::all(registry.texture("water"))).transparent().non_solid())
+        ];
+
+        for (expected, block) in defaults {
+            let id = registry.register(block).expect("duplicate default block");
+            debug_assert_eq!(id, expected);
+        }
+
+        registry
+    }
+
+    /// Adds a new block type to the registry, returning the ID it was assigned
+    pub fn register(&mut self, block: Block) -> Result<BlockId, RegistryError> {
+        if self.names.contains_key(&block.name) {
+            return Err(RegistryError::DuplicateName(block.name));
+        }
+
+        let id = u16::try_from(self.blocks.len())
+            .map(BlockId)
+            .map_err(|_| RegistryError::RegistryFull)?;
+
+        log::debug!("registered block '{}' as {:?}", block.name, id);
+
+        self.names.insert(block.name.clone(), id);
+        self.blocks.push(block);
+
+        Ok(id)
+    }
+
+    /// Gets the ID of the texture with the given name, adding it if this is the first block to use it
+    pub fn texture(&mut self, name: &str) -> TextureId {
+        let index = self.textures.iter()
+            .position(|texture| texture == name)
+            .unwrap_or_else(|| {
+                self.textures.push(name.to_string());
+                self.textures.len() - 1
+            });
+
+        TextureId(index as u32)
+    }
+
+    /// The names of every texture used by a block, indexed by [TextureId]
+    pub fn texture_names(&self) -> &[String] {
+        &self.textures
+    }
+
+    /// Looks up the properties of a block. Unknown IDs are treated as air.
+    pub fn get(&self, id: BlockId) -> &Block {
+        self.blocks.get(id.0 as usize)
+            .unwrap_or(&self.blocks[BlockId::AIR.0 as usize])
+    }
+}
@@ -0,0 +1,475 @@
+use std::collections::VecDeque;
+
+use glam::{IVec3, ivec3};
+
+use crate::renderer::Face;
+
+use super::{World, CHUNK_SIZE};
+
+const VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// The brightest a voxel can be lit
+pub const MAX_LIGHT: u8 = 15;
+
+/// The sky light and block light levels of a voxel, each from 0 to [MAX_LIGHT], packed into one byte
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Light(u8);
+
+impl Light {
+    /// Full daylight, and no light from blocks
+    pub const SKY: Light = Light::new(MAX_LIGHT, 0);
+
+    pub const fn new(sky: u8, block: u8) -> Light {
+        Light(sky << 4 | block)
+    }
+
+    /// Light from the sky, which travels straight down without fading
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    /// Light from blocks that emit it
+    pub fn block(self) -> u8 {
+        self.0 & 0xf
+    }
+
+    fn get(self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Sky => self.sky(),
+            Channel::Block => self.block()
+        }
+    }
+
+    fn with(self, channel: Channel, level: u8) -> Light {
+        match channel {
+            Channel::Sky => Light::new(level, self.block()),
+            Channel::Block => Light::new(self.sky(), level)
+        }
+    }
+}
+
+/// The two kinds of light, which spread independently of each other
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Channel {
+    Sky,
+    Block
+}
+
+/// The light level of every voxel in a chunk. Chunks lit the same throughout, such as those in open sky or deep
+/// underground, don't store a level per voxel.
+pub enum LightStorage {
+    Uniform(Light),
+    Full(Box<[Light]>)
+}
+
+impl LightStorage {
+    fn index(pos: IVec3) -> usize {
+        (pos.x * CHUNK_SIZE * CHUNK_SIZE + pos.y * CHUNK_SIZE + pos.z) as usize
+    }
+
+    pub fn get(&self, pos: IVec3) -> Light {
+        match self {
+            LightStorage::Uniform(light) => *light,
+            LightStorage::Full(levels) => levels[LightStorage::index(pos)]
+        }
+    }
+
+    pub fn set(&mut self, pos: IVec3, light: Light) {
+        match self {
+            LightStorage::Uniform(current) if *current == light => (),
+            LightStorage::Uniform(current) => {
+                let mut levels = vec![*current; VOLUME].into_boxed_slice();
+                levels[LightStorage::index(pos)] = light;
+
+                *self = LightStorage::Full(levels);
+            },
+            LightStorage::Full(levels) => levels[LightStorage::index(pos)] = light
+        }
+    }
+
+    /// Switches back to uniform storage if every voxel has the same light level
+    pub fn compact(&mut self) {
+        if let LightStorage::Full(levels) = self {
+            if levels.iter().all(|&light| light == levels[0]) {
+                *self = LightStorage::Uniform(levels[0]);
+            }
+        }
+    }
+
+    /// The number of bytes allocated on the heap
+    pub fn heap_size(&self) -> usize {
+        match self {
+            LightStorage::Uniform(_) => 0,
+            LightStorage::Full(levels) => levels.len() * std::mem::size_of::<Light>()
+        }
+    }
+}
+
+/// Light spreads by flood fill: each voxel that isn't opaque is lit one level less than its brightest neighbour, or by
+/// the block itself if it emits light. Sky light at full strength also spreads straight down without fading, so that
+/// anything open to the sky is fully lit.
+///
+/// Light never spreads into chunks that aren't loaded. The space above a chunk that has nothing loaded above it is
+/// treated as open sky.
+impl World {
+    /// The light level at a world position, or `None` if the chunk containing it is not loaded
+    pub fn light(&self, pos: IVec3) -> Option<Light> {
+        let (chunk, local) = World::split_pos(pos);
+        self.chunks.get(&chunk).map(|c| c.light(local))
+    }
+
+    fn light_level(&self, pos: IVec3, channel: Channel) -> Option<u8> {
+        self.light(pos).map(|light| light.get(channel))
+    }
+
+    /// Sets one channel of the light at a loaded world position, marking every mesh that can see it as dirty
+    fn set_light_level(&mut self, pos: IVec3, channel: Channel, level: u8) {
+        let (chunk, local) = World::split_pos(pos);
+
+        let Some(target) = self.chunks.get_mut(&chunk) else {
+            return;
+        };
+
+        let light = target.light(local);
+        if light.get(channel) != level {
+            target.set_light(local, light.with(channel, level));
+            self.mark_block_dirty(pos);
+        }
+    }
+
+    fn is_opaque(&self, pos: IVec3) -> bool {
+        self.registry.get(self.get_block(pos)).opaque
+    }
+
+    fn emission(&self, pos: IVec3, channel: Channel) -> u8 {
+        match channel {
+            Channel::Sky => 0,
+            Channel::Block => self.registry.get(self.get_block(pos)).light_emission
+        }
+    }
+
+    /// Whether the top of the chunk column at `pos` is open to the sky, because nothing is loaded above it
+    fn sky_above(&self, pos: IVec3) -> bool {
+        self.light(pos + IVec3::Y).is_none()
+    }
+
+    /// Lights a chunk that has just been added to the world, letting light in from its neighbours and letting its own
+    /// light out into them
+    pub(super) fn light_new_chunk(&mut self, pos: IVec3) {
+        let origin = World::chunk_origin(pos);
+        let mut sky = VecDeque::new();
+        let mut block = VecDeque::new();
+
+        // sunlight falling straight down each column
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let top = origin + ivec3(x, CHUNK_SIZE - 1, z);
+
+                let open = self.sky_above(top) || self.light_level(top + IVec3::Y, Channel::Sky) == Some(MAX_LIGHT);
+                if !open {
+                    continue;
+                }
+
+                for y in (0..CHUNK_SIZE).rev() {
+                    let voxel = origin + ivec3(x, y, z);
+
+                    if self.is_opaque(voxel) {
+                        break;
+                    }
+
+                    self.set_light_level(voxel, Channel::Sky, MAX_LIGHT);
+                    sky.push_back(voxel);
+                }
+            }
+        }
+
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let voxel = origin + ivec3(x, y, z);
+                    let emission = self.emission(voxel, Channel::Block);
+
+                    if emission > 0 {
+                        self.set_light_level(voxel, Channel::Block, emission);
+                        block.push_back(voxel);
+                    }
+                }
+            }
+        }
+
+        // light already in the neighbouring chunks flows in across the borders
+        for face in Face::ALL {
+            let normal = face.normal();
+            let (a, b) = face.tangents();
+            // the layer of the neighbour that touches this chunk
+            let layer = if normal.cmpgt(IVec3::ZERO).any() { origin + normal * CHUNK_SIZE } else { origin + normal };
+
+            for i in 0..CHUNK_SIZE {
+                for j in 0..CHUNK_SIZE {
+                    let voxel = layer + a * i + b * j;
+
+                    if let Some(light) = self.light(voxel) {
+                        if light.sky() > 0 {
+                            sky.push_back(voxel);
+                        }
+                        if light.block() > 0 {
+                            block.push_back(voxel);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.propagate(Channel::Sky, sky);
+        self.propagate(Channel::Block, block);
+
+        // the chunk below was lit as if it were open to the sky; take that back wherever this chunk blocks it
+        let mut removed = VecDeque::new();
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let bottom = origin + ivec3(x, 0, z);
+                let below = bottom - IVec3::Y;
+
+                if self.light_level(bottom, Channel::Sky) != Some(MAX_LIGHT)
+                    && self.light_level(below, Channel::Sky) == Some(MAX_LIGHT) {
+                    self.set_light_level(below, Channel::Sky, 0);
+                    removed.push_back((below, MAX_LIGHT));
+                }
+            }
+        }
+
+        let relit = self.unpropagate(Channel::Sky, removed);
+        self.propagate(Channel::Sky, relit);
+
+        if let Some(chunk) = self.chunks.get_mut(&pos) {
+            chunk.compact_light();
+        }
+    }
+
+    /// Updates the light around a block that has just changed, removing light that it now blocks or that it used to
+    /// give off, and spreading any light it now lets through or gives off
+    pub(super) fn relight_block(&mut self, pos: IVec3) {
+        for channel in [Channel::Sky, Channel::Block] {
+            let Some(old) = self.light_level(pos, channel) else {
+                return;
+            };
+
+            let emission = self.emission(pos, channel);
+            self.set_light_level(pos, channel, emission);
+
+            let mut relit = self.unpropagate(channel, VecDeque::from([(pos, old)]));
+
+            if emission > 0 {
+                relit.push_back(pos);
+            }
+
+            if !self.is_opaque(pos) {
+                if channel == Channel::Sky && self.sky_above(pos) {
+                    self.set_light_level(pos, channel, MAX_LIGHT);
+                    relit.push_back(pos);
+                }
+
+                // light can now reach this block from any of its neighbours
+                relit.extend(Face::ALL.iter().map(|face| pos + face.normal()));
+            }
+
+            self.propagate(channel, relit);
+        }
+    }
+
+    /// The level that light at `level` spreads to the neighbour in the direction of `face`
+    fn spread(channel: Channel, face: Face, level: u8) -> u8 {
+        if channel == Channel::Sky && face == Face::Down && level == MAX_LIGHT {
+            MAX_LIGHT
+        } else {
+            level.saturating_sub(1)
+        }
+    }
+
+    /// Spreads light outwards from each of the queued voxels, until it fades out or is blocked
+    fn propagate(&mut self, channel: Channel, mut queue: VecDeque<IVec3>) {
+        while let Some(pos) = queue.pop_front() {
+            let Some(level) = self.light_level(pos, channel) else {
+                continue;
+            };
+
+            if level == 0 {
+                continue;
+            }
+
+            for face in Face::ALL {
+                let neighbour = pos + face.normal();
+                let spread = World::spread(channel, face, level);
+
+                let Some(current) = self.light_level(neighbour, channel) else {
+                    continue;
+                };
+
+                if current < spread && !self.is_opaque(neighbour) {
+                    self.set_light_level(neighbour, channel, spread);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    /// Removes the light that spread from each of the queued voxels, given as the voxel and the level it used to have.
+    /// The voxels themselves should already have been darkened.
+    ///
+    /// Returns the voxels bordering the darkened area that are lit from elsewhere, which need to be
+    /// [propagated](World::propagate) to fill the area back in.
+    fn unpropagate(&mut self, channel: Channel, mut queue: VecDeque<(IVec3, u8)>) -> VecDeque<IVec3> {
+        let mut relit = VecDeque::new();
+
+        while let Some((pos, old)) = queue.pop_front() {
+            for face in Face::ALL {
+                let neighbour = pos + face.normal();
+
+                let Some(current) = self.light_level(neighbour, channel) else {
+                    continue;
+                };
+
+                if current == 0 {
+                    continue;
+                }
+
+                // the neighbour's light could have come from this voxel, so it has to go too
+                if current < old || current == World::spread(channel, face, old) {
+                    let emission = self.emission(neighbour, channel);
+                    self.set_light_level(neighbour, channel, emission);
+                    queue.push_back((neighbour, current));
+
+                    if emission > 0 {
+                        relit.push_back(neighbour);
+                    }
+                } else {
+                    relit.push_back(neighbour);
+                }
+            }
+        }
+
+        relit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{BlockRegistry, BlockId, Chunk, blocks};
+
+    /// A 3x3 square of air chunks, with a stone floor at y = 0
+    fn floored_world() -> World {
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+
+        for x in -1..=1 {
+            for z in -1..=1 {
+                world.insert_chunk(ivec3(x, 0, z), Chunk::new());
+            }
+        }
+
+        for x in -CHUNK_SIZE..CHUNK_SIZE * 2 {
+            for z in -CHUNK_SIZE..CHUNK_SIZE * 2 {
+                world.set_block(ivec3(x, 0, z), blocks::STONE);
+            }
+        }
+
+        world
+    }
+
+    fn sky(world: &World, pos: IVec3) -> u8 {
+        world.light(pos).unwrap().sky()
+    }
+
+    fn block(world: &World, pos: IVec3) -> u8 {
+        world.light(pos).unwrap().block()
+    }
+
+    #[test]
+    fn open_air_is_sunlit() {
+        let world = floored_world();
+
+        assert_eq!(sky(&world, ivec3(3, 5, 3)), MAX_LIGHT);
+        assert_eq!(sky(&world, ivec3(3, 1, 3)), MAX_LIGHT);
+        assert_eq!(sky(&world, ivec3(3, 0, 3)), 0);
+    }
+
+    #[test]
+    fn roof_shades_and_unshades() {
+        let mut world = floored_world();
+
+        // a 5x5 roof at y = 4, centred on (8, 4, 8)
+        for x in 6..=10 {
+            for z in 6..=10 {
+                world.set_block(ivec3(x, 4, z), blocks::STONE);
+            }
+        }
+
+        // light reaches under the roof from the sides, fading towards the middle
+        assert_eq!(sky(&world, ivec3(8, 2, 8)), MAX_LIGHT - 3);
+        assert_eq!(sky(&world, ivec3(10, 2, 8)), MAX_LIGHT - 1);
+        assert_eq!(sky(&world, ivec3(8, 5, 8)), MAX_LIGHT);
+
+        for x in 6..=10 {
+            for z in 6..=10 {
+                world.set_block(ivec3(x, 4, z), BlockId::AIR);
+            }
+        }
+
+        assert_eq!(sky(&world, ivec3(8, 2, 8)), MAX_LIGHT);
+    }
+
+    #[test]
+    fn lamp_lights_across_chunks() {
+        let mut world = floored_world();
+
+        // one block away from the border with the chunk at x = 1
+        let lamp = ivec3(CHUNK_SIZE - 2, 3, 4);
+        world.set_block(lamp, blocks::LAMP);
+
+        assert_eq!(block(&world, lamp), MAX_LIGHT);
+        assert_eq!(block(&world, lamp + ivec3(3, 0, 0)), MAX_LIGHT - 3);
+        assert_eq!(block(&world, lamp + ivec3(2, 2, 1)), MAX_LIGHT - 5);
+        assert_eq!(block(&world, lamp + ivec3(-15, 0, 0)), 0);
+
+        world.set_block(lamp, BlockId::AIR);
+
+        assert_eq!(block(&world, lamp), 0);
+        assert_eq!(block(&world, lamp + ivec3(3, 0, 0)), 0);
+    }
+
+    #[test]
+    fn removing_one_of_two_lamps() {
+        let mut world = floored_world();
+
+        world.set_block(ivec3(2, 3, 2), blocks::LAMP);
+        world.set_block(ivec3(8, 3, 2), blocks::LAMP);
+
+        assert_eq!(block(&world, ivec3(5, 3, 2)), MAX_LIGHT - 3);
+
+        world.set_block(ivec3(2, 3, 2), BlockId::AIR);
+
+        // still lit by the other lamp
+        assert_eq!(block(&world, ivec3(5, 3, 2)), MAX_LIGHT - 3);
+        assert_eq!(block(&world, ivec3(2, 3, 2)), MAX_LIGHT - 6);
+    }
+
+    #[test]
+    fn chunk_loaded_above_casts_shadow() {
+        let mut world = floored_world();
+
+        let mut roof = Chunk::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                roof.set(ivec3(x, 0, z), blocks::STONE);
+            }
+        }
+
+        world.insert_chunk(ivec3(0, 1, 0), roof);
+
+        // the middle of the covered chunk is only lit from the chunks beside it
+        assert!(sky(&world, ivec3(8, 5, 8)) < MAX_LIGHT - 5);
+        assert_eq!(sky(&world, ivec3(-1, 5, 8)), MAX_LIGHT);
+        assert_eq!(sky(&world, ivec3(0, 5, 8)), MAX_LIGHT - 1);
+    }
+}
@@ -1,74 +1,313 @@
-use crate::renderer::{Mesh, MeshBuilder, CubeFaces};
-use glam::{vec3, IVec3, ivec3};
+pub mod block;
+pub mod padded;
+pub mod terrain;
+pub mod streaming;
+pub mod region;
+pub mod palette;
+pub mod raycast;
+pub mod physics;
+pub mod light;
 
-pub struct Chunk {
-    blocks: [[[bool; 16]; 16]; 16]
+pub use block::*;
+pub use padded::*;
+pub use terrain::*;
+pub use streaming::*;
+pub use region::*;
+pub use palette::*;
+pub use physics::*;
+pub use light::*;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use glam::{IVec3, Vec3, ivec3};
+
+/// The length of each side of a [Chunk], in blocks
+pub const CHUNK_SIZE: i32 = 16;
+
+/// A world made up of any number of [Chunk]s, indexed by chunk coordinate. A chunk at coordinate `c` contains the
+/// blocks from `c * CHUNK_SIZE` up to, but not including, `(c + 1) * CHUNK_SIZE`.
+pub struct World {
+    registry: Arc<BlockRegistry>,
+    chunks: HashMap<IVec3, Chunk>,
+    /// Chunks that have changed in a way that affects their mesh since the last call to [World::take_dirty]
+    dirty: HashSet<IVec3>,
+    /// How every chunk is meshed, including chunks added later
+    meshing: MeshingMode
 }
 
-impl Chunk {
-    pub fn new() -> Chunk {
-        let mut chunk = Chunk { blocks: [[[false; 16]; 16]; 16] };
+impl World {
+    pub fn new(registry: BlockRegistry) -> World {
+        World { registry: Arc::new(registry), chunks: HashMap::new(), dirty: HashSet::new(), meshing: MeshingMode::default() }
+    }
+
+    /// The world's block registry, shared so that it can be used from other threads
+    pub fn registry(&self) -> &Arc<BlockRegistry> { &self.registry }
+
+    /// Splits a world block position into the coordinate of the chunk it lies in, and its position within that chunk
+    pub fn split_pos(pos: IVec3) -> (IVec3, IVec3) {
+        let chunk = ivec3(pos.x.div_euclid(CHUNK_SIZE), pos.y.div_euclid(CHUNK_SIZE), pos.z.div_euclid(CHUNK_SIZE));
+        (chunk, pos - chunk * CHUNK_SIZE)
+    }
+
+    /// The position of the block containing a point. Blocks are centred on their integer coordinates.
+    pub fn block_at_point(point: Vec3) -> IVec3 {
+        (point + Vec3::splat(0.5)).floor().as_ivec3()
+    }
 
-        chunk.blocks[11][0][10] = true;
-        chunk.blocks[10][0][10] = true;
-        chunk.blocks[10][0][11] = true;
-        chunk.blocks[10][0][12] = true;
-        chunk.blocks[10][0][13] = true;
-        chunk.blocks[10][0][14] = true;
-        chunk.blocks[11][0][15] = true;
-        chunk.blocks[10][0][15] = true;
+    /// The world position of the first block in a chunk
+    pub fn chunk_origin(chunk: IVec3) -> IVec3 {
+        chunk * CHUNK_SIZE
+    }
 
-        chunk.blocks[13][0][11] = true;
-        chunk.blocks[14][0][11] = true;
-        chunk.blocks[15][0][11] = true;
+    pub fn chunk(&self, pos: IVec3) -> Option<&Chunk> {
+        self.chunks.get(&pos)
+    }
 
-        chunk.blocks[13][0][14] = true;
-        chunk.blocks[14][0][14] = true;
-        chunk.blocks[15][0][14] = true;
+    pub fn chunk_positions(&self) -> impl Iterator<Item = IVec3> + '_ {
+        self.chunks.keys().copied()
+    }
 
-        chunk
+    /// Returns the chunks that need to be remeshed, clearing the list
+    pub fn take_dirty(&mut self) -> Vec<IVec3> {
+        self.dirty.drain().collect()
     }
 
-    pub fn get_faces(&self, pos: IVec3) -> CubeFaces {
-        if !self.blocks[pos.x as usize][pos.y as usize][pos.z as usize] {
-            return CubeFaces::from_world_state(false, false, false, false, false, false);
+    /// Marks a chunk as needing to be remeshed, if it is loaded
+    fn mark_dirty(&mut self, pos: IVec3) {
+        if self.chunks.contains_key(&pos) {
+            self.dirty.insert(pos);
         }
+    }
 
-        let up = if pos.y == 15 { true } else {
-            !self.blocks[pos.x as usize][pos.y as usize + 1][pos.z as usize]
-        };
-        let down = if pos.y == 0 { true } else {
-            !self.blocks[pos.x as usize][pos.y as usize - 1][pos.z as usize]
-        };
+    /// Marks a chunk as dirty along with all 26 of its neighbours, whose borders may have been affected by it changing;
+    /// ambient occlusion means that even chunks touching only at an edge or corner can see each other's blocks
+    fn mark_neighbourhood_dirty(&mut self, pos: IVec3) {
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    self.mark_dirty(pos + ivec3(x, y, z));
+                }
+            }
+        }
+    }
 
-        let north = if pos.z == 15 { true } else {
-            !self.blocks[pos.x as usize][pos.y as usize][pos.z as usize + 1]
-        };
-        let south = if pos.z == 0 { true } else {
-            !self.blocks[pos.x as usize][pos.y as usize][pos.z as usize - 1]
-        };
-        let east = if pos.x == 15 { true } else {
-            !self.blocks[pos.x as usize + 1][pos.y as usize][pos.z as usize]
-        };
-        let west = if pos.x == 0 { true } else {
-            !self.blocks[pos.x as usize - 1][pos.y as usize][pos.z as usize]
-        };
+    pub fn meshing(&self) -> MeshingMode { self.meshing }
+
+    /// Changes how every chunk is meshed, marking the loaded ones as dirty. Chunks added later are meshed the same way.
+    pub fn set_meshing(&mut self, mode: MeshingMode) {
+        self.meshing = mode;
 
-        CubeFaces::from_world_state(up, down, north, south, east, west)
+        for (pos, chunk) in self.chunks.iter_mut() {
+            chunk.set_meshing(mode);
+            self.dirty.insert(*pos);
+        }
     }
 
-    pub fn build_mesh(&self, device: &wgpu::Device) -> Mesh {
-        let mut builder = MeshBuilder::new();
+    /// Marks every chunk whose mesh can see the block at `pos` as dirty. As well as the chunk containing it, blocks on
+    /// the border of a chunk are visible to the neighbouring chunks' meshes, including the chunks across an edge or
+    /// corner when the block lies on one.
+    fn mark_block_dirty(&mut self, pos: IVec3) {
+        let (chunk, local) = World::split_pos(pos);
 
-        for x in 0..16 {
-            for y in 0..16 {
-                for z in 0..16 {
-                    let faces = self.get_faces(ivec3(x as i32, y as i32, z as i32));
-                    builder.add_cube(vec3(x as f32, y as f32, z as f32), faces);
+        let range = |local: i32| {
+            let low = if local == 0 { -1 } else { 0 };
+            let high = if local == CHUNK_SIZE - 1 { 1 } else { 0 };
+            low..=high
+        };
+
+        for x in range(local.x) {
+            for y in range(local.y) {
+                for z in range(local.z) {
+                    self.mark_dirty(chunk + ivec3(x, y, z));
                 }
             }
         }
+    }
+
+    /// Adds a chunk to the world, replacing any chunk already at `pos`, and lights it. The chunk is switched to the
+    /// world's [MeshingMode].
+    pub fn insert_chunk(&mut self, pos: IVec3, mut chunk: Chunk) {
+        chunk.set_meshing(self.meshing);
+        self.chunks.insert(pos, chunk);
+        self.mark_neighbourhood_dirty(pos);
+        self.light_new_chunk(pos);
+    }
+
+    /// Removes a chunk from the world. The removed chunk is marked as dirty, so that its mesh is dropped.
+    pub fn remove_chunk(&mut self, pos: IVec3) -> Option<Chunk> {
+        let chunk = self.chunks.remove(&pos)?;
+
+        self.mark_neighbourhood_dirty(pos);
+        self.dirty.insert(pos);
+
+        Some(chunk)
+    }
+
+    /// The chunks that have been edited since they were last saved
+    pub fn modified_chunks(&self) -> impl Iterator<Item = (IVec3, &Chunk)> {
+        self.chunks.iter()
+            .filter(|(_, chunk)| chunk.modified)
+            .map(|(pos, chunk)| (*pos, chunk))
+    }
+
+    /// Clears the modified flag of every loaded chunk, once they have all been saved
+    pub fn mark_saved(&mut self) {
+        for chunk in self.chunks.values_mut() {
+            chunk.modified = false;
+        }
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats { chunks: self.chunks.len(), single_block_chunks: 0, bytes: 0 };
+
+        for chunk in self.chunks.values() {
+            stats.bytes += chunk.memory_usage();
+
+            if chunk.blocks.is_single() {
+                stats.single_block_chunks += 1;
+            }
+        }
+
+        stats
+    }
+
+    /// Gets the block at a world position. Blocks in chunks that are not loaded are treated as air.
+    pub fn get_block(&self, pos: IVec3) -> BlockId {
+        let (chunk, local) = World::split_pos(pos);
+
+        self.chunks.get(&chunk)
+            .map(|c| c.get(local))
+            .unwrap_or(BlockId::AIR)
+    }
+
+    /// Whether entities collide with the block at a world position. Blocks in chunks that are not loaded count as
+    /// solid, so that nothing falls out of the world before it has loaded.
+    pub fn is_solid(&self, pos: IVec3) -> bool {
+        let (chunk, local) = World::split_pos(pos);
+
+        self.chunks.get(&chunk)
+            .map(|c| self.registry.get(c.get(local)).solid)
+            .unwrap_or(true)
+    }
+
+    /// Sets the block at a world position. Returns false, leaving the world unchanged, if the chunk containing `pos`
+    /// is not loaded.
+    pub fn set_block(&mut self, pos: IVec3, id: BlockId) -> bool {
+        let (chunk, local) = World::split_pos(pos);
+
+        let Some(target) = self.chunks.get_mut(&chunk) else {
+            return false;
+        };
+
+        target.set(local, id);
+        target.modified = true;
+
+        self.mark_block_dirty(pos);
+        self.relight_block(pos);
+
+        true
+    }
+}
+
+/// How much memory the loaded chunks of a [World] are using
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryStats {
+    pub chunks: usize,
+    /// Chunks made entirely of one block type, which don't need to store their blocks
+    pub single_block_chunks: usize,
+    pub bytes: usize
+}
+
+impl MemoryStats {
+    /// The memory the chunks would use if every block ID was stored separately
+    pub fn flat_bytes(&self) -> usize {
+        self.chunks * (std::mem::size_of::<Chunk>() + (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize * std::mem::size_of::<BlockId>())
+    }
+}
+
+impl std::fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "{} chunks ({} single block) using {} KiB, {} KiB if stored flat",
+            self.chunks, self.single_block_chunks, self.bytes / 1024, self.flat_bytes() / 1024
+        )
+    }
+}
+
+pub struct Chunk {
+    blocks: BlockStorage,
+    /// Worked out when the chunk is added to a [World], rather than saved with it
+    light: LightStorage,
+    meshing: MeshingMode,
+    /// Whether the chunk has been edited since it was generated or last saved
+    modified: bool
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk { blocks: BlockStorage::new(BlockId::AIR), light: LightStorage::Uniform(Light::default()), meshing: MeshingMode::default(), modified: false }
+    }
+
+    pub fn is_modified(&self) -> bool { self.modified }
+
+    pub fn meshing(&self) -> MeshingMode { self.meshing }
+
+    pub fn set_meshing(&mut self, mode: MeshingMode) {
+        self.meshing = mode;
+    }
+
+    pub fn get(&self, pos: IVec3) -> BlockId {
+        self.blocks.get(pos)
+    }
+
+    pub fn set(&mut self, pos: IVec3, id: BlockId) {
+        self.blocks.set(pos, id);
+    }
+
+    pub fn light(&self, pos: IVec3) -> Light {
+        self.light.get(pos)
+    }
+
+    fn set_light(&mut self, pos: IVec3, light: Light) {
+        self.light.set(pos, light);
+    }
+
+    fn compact_light(&mut self) {
+        self.light.compact();
+    }
+
+    /// Shrinks the chunk's storage after a batch of edits, such as generating it
+    pub fn compact(&mut self) {
+        self.blocks.compact();
+    }
+
+    /// The number of bytes used to store this chunk, including its blocks and light
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Chunk>() + self.blocks.heap_size() + self.light.heap_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserting_chunk_dirties_neighbours() {
+        let faces = [ivec3(1, 0, 0), ivec3(-1, 0, 0), ivec3(0, 1, 0), ivec3(0, -1, 0), ivec3(0, 0, 1), ivec3(0, 0, -1)];
+
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+        for pos in faces {
+            world.insert_chunk(pos, Chunk::new());
+        }
+        world.take_dirty();
+
+        world.insert_chunk(IVec3::ZERO, Chunk::new());
+        let dirty = world.take_dirty();
 
-        builder.build(device)
+        assert!(dirty.contains(&IVec3::ZERO));
+        assert!(faces.iter().all(|pos| dirty.contains(pos)));
+        // chunks that aren't loaded have no mesh to rebuild
+        assert!(!dirty.contains(&ivec3(1, 1, 0)));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,391 @@
+use std::time::Instant;
+
+use glam::{vec3, IVec3, ivec3, UVec2};
+
+use crate::renderer::{self, MeshData, MeshBuilder, CubeFaces, Face, FaceShading, MAX_OCCLUSION};
+
+use super::{World, BlockId, BlockRegistry, Light, CHUNK_SIZE};
+
+/// The length of each side of a [PaddedChunk]
+const PADDED_SIZE: i32 = CHUNK_SIZE + 2;
+
+/// How a chunk's blocks are turned into a mesh
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum MeshingMode {
+    /// Every visible face becomes its own quad
+    Naive,
+    /// Adjacent, coplanar faces with the same texture and shading are merged into the largest rectangles possible
+    #[default]
+    Greedy
+}
+
+/// A copy of a chunk's blocks and light, surrounded by a one block border copied from its neighbouring chunks. This
+/// lets the chunk be meshed without access to the rest of the world, while still culling and shading faces against its
+/// neighbours.
+pub struct PaddedChunk {
+    blocks: Box<[BlockId]>,
+    light: Box<[Light]>,
+    meshing: MeshingMode
+}
+
+impl PaddedChunk {
+    /// Copies the chunk at `pos` along with its border. Returns `None` if the chunk is not loaded; neighbouring
+    /// chunks that are not loaded are treated as sunlit air.
+    pub fn from_world(world: &World, pos: IVec3) -> Option<PaddedChunk> {
+        let meshing = world.chunk(pos)?.meshing();
+
+        // look up each of the 27 chunks in the neighbourhood once, rather than once per block
+        let mut neighbours = [None; 27];
+        for (i, neighbour) in neighbours.iter_mut().enumerate() {
+            let offset = ivec3(i as i32 % 3, i as i32 / 3 % 3, i as i32 / 9) - IVec3::ONE;
+            *neighbour = world.chunk(pos + offset);
+        }
+
+        let mut blocks = vec![BlockId::AIR; (PADDED_SIZE * PADDED_SIZE * PADDED_SIZE) as usize].into_boxed_slice();
+        let mut light = vec![Light::SKY; blocks.len()].into_boxed_slice();
+
+        for x in -1..=CHUNK_SIZE {
+            for y in -1..=CHUNK_SIZE {
+                for z in -1..=CHUNK_SIZE {
+                    let (offset, local) = World::split_pos(ivec3(x, y, z));
+                    let index = offset + IVec3::ONE;
+
+                    if let Some(chunk) = neighbours[(index.x + index.y * 3 + index.z * 9) as usize] {
+                        blocks[PaddedChunk::index(ivec3(x, y, z))] = chunk.get(local);
+                        light[PaddedChunk::index(ivec3(x, y, z))] = chunk.light(local);
+                    }
+                }
+            }
+        }
+
+        Some(PaddedChunk { blocks, light, meshing })
+    }
+
+    fn index(pos: IVec3) -> usize {
+        let pos = pos + IVec3::ONE;
+        (pos.x * PADDED_SIZE * PADDED_SIZE + pos.y * PADDED_SIZE + pos.z) as usize
+    }
+
+    /// Gets a block relative to the chunk's first block. Each component of `pos` must lie in `-1..=CHUNK_SIZE`.
+    pub fn get(&self, pos: IVec3) -> BlockId {
+        self.blocks[PaddedChunk::index(pos)]
+    }
+
+    /// Whether the `face` side of the block at `pos` can be seen
+    fn is_face_visible(&self, pos: IVec3, face: Face, registry: &BlockRegistry) -> bool {
+        let id = self.get(pos);
+
+        if id.is_air() {
+            return false;
+        }
+
+        // a face is hidden by an opaque neighbour, or by a neighbour of the same type (e.g. glass next to glass)
+        let neighbour = self.get(pos + face.normal());
+        !registry.get(neighbour).opaque && neighbour != id
+    }
+
+    /// How each corner of the `face` side of the block at `pos` is shaded, from the four blocks touching that corner in
+    /// front of the face. Opaque blocks occlude the corner, and the light of the others is averaged.
+    fn shading(&self, pos: IVec3, face: Face, registry: &BlockRegistry) -> FaceShading {
+        let (a, b) = face.tangents();
+        let front = pos + face.normal();
+        let opaque = |offset: IVec3| registry.get(self.get(front + offset)).opaque;
+
+        let corners = [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(sa, sb)| {
+            let side_a = opaque(a * sa);
+            let side_b = opaque(b * sb);
+            // a corner between two blocks is fully hidden, whether or not the diagonal block is there
+            let diagonal = (side_a && side_b) || opaque(a * sa + b * sb);
+
+            let occlusion = if side_a && side_b {
+                0
+            } else {
+                MAX_OCCLUSION - side_a as u8 - side_b as u8 - diagonal as u8
+            };
+
+            let lit = [(IVec3::ZERO, false), (a * sa, side_a), (b * sb, side_b), (a * sa + b * sb, diagonal)]
+                .into_iter()
+                .filter(|&(_, opaque)| !opaque)
+                .map(|(offset, _)| self.light[PaddedChunk::index(front + offset)]);
+
+            let (mut sky, mut block, mut count) = (0, 0, 0);
+            for light in lit {
+                sky += light.sky() as u32;
+                block += light.block() as u32;
+                count += 1;
+            }
+
+            let average = |total: u32| ((total + count / 2) / count) as u8;
+            (occlusion, Light::new(average(sky), average(block)))
+        });
+
+        FaceShading { occlusion: corners.map(|(occlusion, _)| occlusion), light: corners.map(|(_, light)| light) }
+    }
+
+    pub fn get_faces(&self, pos: IVec3, registry: &BlockRegistry) -> CubeFaces {
+        let visible = |face: Face| self.is_face_visible(pos, face, registry);
+
+        CubeFaces::from_world_state(
+            visible(Face::Up), visible(Face::Down),
+            visible(Face::North), visible(Face::South),
+            visible(Face::East), visible(Face::West)
+        )
+    }
+
+    /// Builds the mesh for this chunk using its [MeshingMode], with its first block placed at `origin` in world space
+    pub fn build_mesh(&self, origin: IVec3, registry: &BlockRegistry) -> Result<MeshData, renderer::Error> {
+        let mut builder = MeshBuilder::new();
+        let start = Instant::now();
+
+        match self.meshing {
+            MeshingMode::Naive => self.mesh_naive(origin, registry, &mut builder)?,
+            MeshingMode::Greedy => self.mesh_greedy(origin, registry, &mut builder)?
+        }
+
+        log::debug!("meshed chunk at {} ({:?}): {} vertices in {:?}", origin, self.meshing, builder.vertex_count(), start.elapsed());
+
+        Ok(builder.build())
+    }
+
+    fn mesh_naive(&self, origin: IVec3, registry: &BlockRegistry, builder: &mut MeshBuilder) -> Result<(), renderer::Error> {
+        let origin = origin.as_vec3();
+
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let pos = ivec3(x, y, z);
+                    let faces = self.get_faces(pos, registry);
+                    let block = registry.get(self.get(pos));
+
+                    builder.add_cube(
+                        origin + vec3(x as f32, y as f32, z as f32), faces, &block.textures, !block.opaque,
+                        |face| self.shading(pos, face, registry)
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mesh_greedy(&self, origin: IVec3, registry: &BlockRegistry, builder: &mut MeshBuilder) -> Result<(), renderer::Error> {
+        const SIZE: usize = CHUNK_SIZE as usize;
+
+        for face in Face::ALL {
+            let (a_axis, b_axis) = face.tangents();
+            let depth_axis = face.normal().abs();
+
+            for depth in 0..CHUNK_SIZE {
+                // the texture, transparency and shading of each visible face in this slice of the chunk, indexed by
+                // a + b * SIZE; only faces that match in all three can be merged
+                let mut mask = [None; SIZE * SIZE];
+
+                for a in 0..CHUNK_SIZE {
+                    for b in 0..CHUNK_SIZE {
+                        let pos = depth_axis * depth + a_axis * a + b_axis * b;
+
+                        if self.is_face_visible(pos, face, registry) {
+                            let block = registry.get(self.get(pos));
+                            let key = (block.textures.get(face), !block.opaque, self.shading(pos, face, registry));
+                            mask[a as usize + b as usize * SIZE] = Some(key);
+                        }
+                    }
+                }
+
+                for b in 0..SIZE {
+                    let mut a = 0;
+
+                    while a < SIZE {
+                        let Some(key @ (texture, transparent, shading)) = mask[a + b * SIZE] else {
+                            a += 1;
+                            continue;
+                        };
+
+                        let width = (a..SIZE)
+                            .take_while(|&a| mask[a + b * SIZE] == Some(key))
+                            .count();
+
+                        let height = (b..SIZE)
+                            .take_while(|&b| (a..a + width).all(|a| mask[a + b * SIZE] == Some(key)))
+                            .count();
+
+                        for row in b..b + height {
+                            mask[a + row * SIZE..a + width + row * SIZE].fill(None);
+                        }
+
+                        let pos = origin + depth_axis * depth + a_axis * a as i32 + b_axis * b as i32;
+                        let size = UVec2::new(width as u32, height as u32);
+                        builder.add_face(pos.as_vec3(), face, size, texture.0, transparent, shading)?;
+
+                        a += width;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{Chunk, blocks};
+
+    /// A world with one chunk, whose bottom layer is filled by `floor`
+    fn floor_world(floor: impl Fn(i32, i32) -> BlockId) -> World {
+        let mut chunk = Chunk::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.set(ivec3(x, 0, z), floor(x, z));
+            }
+        }
+
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+        world.insert_chunk(IVec3::ZERO, chunk);
+        world
+    }
+
+    /// The UVs of each quad on the top of the floor in `world`
+    fn top_quads(world: &mut World, mode: MeshingMode) -> Vec<[glam::Vec2; 4]> {
+        world.set_meshing(mode);
+        let mesh = PaddedChunk::from_world(world, IVec3::ZERO).unwrap().build_mesh(IVec3::ZERO, world.registry()).unwrap();
+
+        mesh.vertices.chunks(4)
+            .filter(|quad| quad.iter().all(|vertex| vertex.position.y == 0.5))
+            .map(|quad| [quad[0].uv, quad[1].uv, quad[2].uv, quad[3].uv])
+            .collect()
+    }
+
+    #[test]
+    fn greedy_merges_flat_floor() {
+        let mut world = floor_world(|_, _| blocks::STONE);
+
+        assert_eq!(top_quads(&mut world, MeshingMode::Naive).len(), 256);
+
+        let greedy = top_quads(&mut world, MeshingMode::Greedy);
+        assert_eq!(greedy.len(), 1);
+
+        // the UVs span the whole floor, so that the texture repeats once per block
+        for axis in 0..2 {
+            let uvs = greedy[0].map(|uv| uv[axis]);
+            assert_eq!(uvs.iter().copied().fold(f32::INFINITY, f32::min), 0.0);
+            assert_eq!(uvs.iter().copied().fold(f32::NEG_INFINITY, f32::max), 16.0);
+        }
+    }
+
+    #[test]
+    fn greedy_keeps_different_faces_apart() {
+        // stone and dirt halves, which have different textures
+        let mut world = floor_world(|x, _| if x < 8 { blocks::STONE } else { blocks::DIRT });
+        assert_eq!(top_quads(&mut world, MeshingMode::Greedy).len(), 2);
+
+        // a lamp floating above the middle of the floor lights some of it more than the rest
+        let mut world = floor_world(|_, _| blocks::STONE);
+        world.set_block(ivec3(8, 5, 8), blocks::LAMP);
+        assert!(top_quads(&mut world, MeshingMode::Greedy).len() > 1);
+    }
+
+    fn solid_chunk() -> Chunk {
+        let mut chunk = Chunk::new();
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    chunk.set(ivec3(x, y, z), blocks::STONE);
+                }
+            }
+        }
+        chunk
+    }
+
+    /// The number of faces meshed for the chunk at the origin on its border with the chunk to the east
+    fn east_border_faces(world: &World) -> usize {
+        let mesh = PaddedChunk::from_world(world, IVec3::ZERO).unwrap().build_mesh(IVec3::ZERO, world.registry()).unwrap();
+        let border = CHUNK_SIZE as f32 - 0.5;
+
+        mesh.vertices.chunks(4)
+            .filter(|quad| quad.iter().all(|vertex| vertex.position.x == border))
+            .count()
+    }
+
+    #[test]
+    fn transparent_faces_are_kept_apart() {
+        // stone and water halves, so that greedy meshing has the chance to merge them
+        let mut world = floor_world(|x, _| if x < 8 { blocks::STONE } else { blocks::WATER });
+
+        for mode in [MeshingMode::Naive, MeshingMode::Greedy] {
+            world.set_meshing(mode);
+            let mesh = PaddedChunk::from_world(&world, IVec3::ZERO).unwrap().build_mesh(IVec3::ZERO, world.registry()).unwrap();
+
+            // the two halves meet at x = 7.5
+            let x = |&index: &u32| mesh.vertices[index as usize].position.x;
+            assert!(!mesh.indices.is_empty() && mesh.indices.iter().all(|index| x(index) <= 7.5));
+            assert!(!mesh.transparent_indices.is_empty() && mesh.transparent_indices.iter().all(|index| x(index) >= 7.5));
+        }
+    }
+
+    #[test]
+    fn border_faces_culled_against_neighbours() {
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+        world.set_meshing(MeshingMode::Naive);
+        world.insert_chunk(IVec3::ZERO, solid_chunk());
+
+        // with nothing loaded next to it, the border is treated as air
+        assert_eq!(east_border_faces(&world), 256);
+
+        world.insert_chunk(ivec3(1, 0, 0), solid_chunk());
+        assert_eq!(east_border_faces(&world), 0);
+    }
+
+    #[test]
+    fn occlusion_next_to_wall() {
+        let mut chunk = Chunk::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.set(ivec3(x, 0, z), blocks::STONE);
+            }
+        }
+
+        // a wall one block high along x = 5, and a lone block diagonal to (8, 0, 8)
+        for z in 0..CHUNK_SIZE {
+            chunk.set(ivec3(5, 1, z), blocks::STONE);
+        }
+        chunk.set(ivec3(9, 1, 9), blocks::STONE);
+
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+        world.insert_chunk(IVec3::ZERO, chunk);
+
+        let padded = PaddedChunk::from_world(&world, IVec3::ZERO).unwrap();
+        let occlusion = |x, z| padded.shading(ivec3(x, 0, z), Face::Up, world.registry()).occlusion;
+
+        // corners in (-x, -z), (+x, -z), (+x, +z), (-x, +z) order
+        assert_eq!(occlusion(4, 8), [3, 1, 1, 3]);
+        assert_eq!(occlusion(6, 8), [1, 3, 3, 1]);
+        assert_eq!(occlusion(8, 8), [3, 3, 2, 3]);
+        assert_eq!(occlusion(12, 12), [3; 4]);
+    }
+
+    #[test]
+    fn light_averages_around_corners() {
+        let mut chunk = Chunk::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.set(ivec3(x, 0, z), blocks::STONE);
+                chunk.set(ivec3(x, 4, z), blocks::STONE);
+            }
+        }
+
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+        world.insert_chunk(IVec3::ZERO, chunk);
+        world.set_block(ivec3(8, 2, 8), blocks::LAMP);
+
+        let padded = PaddedChunk::from_world(&world, IVec3::ZERO).unwrap();
+        let light = |pos, face| padded.shading(pos, face, world.registry()).light;
+
+        // each corner averages the 14 in front of the face with the 13, 13 and 12 around it
+        assert_eq!(light(ivec3(8, 2, 8), Face::Up), [Light::new(0, 13); 4]);
+        assert_eq!(light(ivec3(8, 0, 8), Face::Up)[0], Light::new(0, 13));
+        // 6, 7, 7 and 8 further away
+        assert_eq!(light(ivec3(12, 0, 12), Face::Up)[0], Light::new(0, 7));
+    }
+}
@@ -0,0 +1,220 @@
+use glam::IVec3;
+
+use super::{BlockId, CHUNK_SIZE};
+
+const VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// Compact storage for the blocks of a chunk. Each block is stored as an index into a palette of the block types used
+/// in the chunk, packed using as few bits as the palette allows. Chunks made of a single block type don't store any
+/// indices at all.
+pub enum BlockStorage {
+    /// Every block in the chunk is the same
+    Single(BlockId),
+    Paletted {
+        palette: Vec<BlockId>,
+        /// The number of bits used by each index
+        bits: u32,
+        /// Packed palette indices; indices never straddle two words
+        data: Vec<u64>
+    }
+}
+
+impl BlockStorage {
+    pub fn new(id: BlockId) -> BlockStorage {
+        BlockStorage::Single(id)
+    }
+
+    fn index(pos: IVec3) -> usize {
+        (pos.x * CHUNK_SIZE * CHUNK_SIZE + pos.y * CHUNK_SIZE + pos.z) as usize
+    }
+
+    /// The number of bits needed to index a palette with `len` entries
+    fn bits_for(len: usize) -> u32 {
+        (usize::BITS - (len - 1).leading_zeros()).max(1)
+    }
+
+    fn read(data: &[u64], bits: u32, index: usize) -> usize {
+        let per_word = (64 / bits) as usize;
+        let shift = (index % per_word) as u32 * bits;
+
+        ((data[index / per_word] >> shift) & ((1 << bits) - 1)) as usize
+    }
+
+    fn write(data: &mut [u64], bits: u32, index: usize, value: usize) {
+        let per_word = (64 / bits) as usize;
+        let shift = (index % per_word) as u32 * bits;
+        let mask = ((1u64 << bits) - 1) << shift;
+
+        let word = &mut data[index / per_word];
+        *word = (*word & !mask) | ((value as u64) << shift);
+    }
+
+    /// Packs a list of palette indices using `bits` bits each
+    fn pack(indices: impl Iterator<Item = usize>, bits: u32) -> Vec<u64> {
+        let per_word = (64 / bits) as usize;
+        let mut data = vec![0; VOLUME.div_ceil(per_word)];
+
+        for (i, value) in indices.enumerate() {
+            BlockStorage::write(&mut data, bits, i, value);
+        }
+
+        data
+    }
+
+    pub fn get(&self, pos: IVec3) -> BlockId {
+        match self {
+            BlockStorage::Single(id) => *id,
+            BlockStorage::Paletted { palette, bits, data } => palette[BlockStorage::read(data, *bits, BlockStorage::index(pos))]
+        }
+    }
+
+    pub fn set(&mut self, pos: IVec3, id: BlockId) {
+        let index = BlockStorage::index(pos);
+
+        match self {
+            BlockStorage::Single(existing) if *existing == id => (),
+            BlockStorage::Single(existing) => {
+                // every block currently refers to palette entry 0, which is all zero bits
+                let mut data = vec![0; VOLUME.div_ceil(64)];
+                BlockStorage::write(&mut data, 1, index, 1);
+
+                *self = BlockStorage::Paletted { palette: vec![*existing, id], bits: 1, data };
+            },
+            BlockStorage::Paletted { palette, bits, data } => {
+                let entry = match palette.iter().position(|&p| p == id) {
+                    Some(entry) => entry,
+                    None => {
+                        palette.push(id);
+
+                        let needed = BlockStorage::bits_for(palette.len());
+                        if needed > *bits {
+                            let old = std::mem::take(data);
+                            *data = BlockStorage::pack((0..VOLUME).map(|i| BlockStorage::read(&old, *bits, i)), needed);
+                            *bits = needed;
+                        }
+
+                        palette.len() - 1
+                    }
+                };
+
+                BlockStorage::write(data, *bits, index, entry);
+            }
+        }
+    }
+
+    /// Drops palette entries that are no longer used, shrinking the indices or collapsing the storage down to a
+    /// single block type where possible
+    pub fn compact(&mut self) {
+        let BlockStorage::Paletted { palette, bits, data } = self else {
+            return;
+        };
+
+        let mut used = vec![false; palette.len()];
+        for i in 0..VOLUME {
+            used[BlockStorage::read(data, *bits, i)] = true;
+        }
+
+        // map each used entry to its index in the new palette
+        let mut remap = vec![0; palette.len()];
+        let mut compacted = vec![];
+
+        for (entry, &id) in palette.iter().enumerate() {
+            if used[entry] {
+                remap[entry] = compacted.len();
+                compacted.push(id);
+            }
+        }
+
+        if compacted.len() == 1 {
+            *self = BlockStorage::Single(compacted[0]);
+        } else if compacted.len() < palette.len() {
+            let new_bits = BlockStorage::bits_for(compacted.len());
+            *data = BlockStorage::pack((0..VOLUME).map(|i| remap[BlockStorage::read(data, *bits, i)]), new_bits);
+            *bits = new_bits;
+            *palette = compacted;
+        }
+    }
+
+    /// The number of bytes allocated on the heap by this storage
+    pub fn heap_size(&self) -> usize {
+        match self {
+            BlockStorage::Single(_) => 0,
+            BlockStorage::Paletted { palette, data, .. } => {
+                palette.capacity() * std::mem::size_of::<BlockId>() + data.capacity() * std::mem::size_of::<u64>()
+            }
+        }
+    }
+
+    pub fn is_single(&self) -> bool {
+        matches!(self, BlockStorage::Single(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::ivec3;
+
+    use super::*;
+
+    fn positions() -> impl Iterator<Item = IVec3> {
+        (0..VOLUME as i32).map(|i| ivec3(i / 256, i / 16 % 16, i % 16))
+    }
+
+    #[test]
+    fn matches_flat_storage() {
+        let mut storage = BlockStorage::new(BlockId::AIR);
+        let mut flat = vec![BlockId::AIR; VOLUME];
+
+        // enough block types to need several resizes of the indices
+        for (i, pos) in positions().enumerate().step_by(7) {
+            let id = BlockId((i % 37) as u16);
+            storage.set(pos, id);
+            flat[i] = id;
+        }
+
+        for (i, pos) in positions().enumerate() {
+            assert_eq!(storage.get(pos), flat[i]);
+        }
+    }
+
+    #[test]
+    fn compacts_to_single_block() {
+        let mut storage = BlockStorage::new(BlockId::AIR);
+
+        for pos in positions() {
+            storage.set(pos, BlockId(4));
+        }
+
+        assert!(!storage.is_single());
+        storage.compact();
+
+        assert!(storage.is_single());
+        assert_eq!(storage.get(ivec3(3, 9, 12)), BlockId(4));
+        assert_eq!(storage.heap_size(), 0);
+    }
+
+    #[test]
+    fn compacting_keeps_blocks() {
+        let mut storage = BlockStorage::new(BlockId::AIR);
+        let pos = |id: i32| ivec3(0, id / CHUNK_SIZE, id % CHUNK_SIZE);
+
+        for id in 1..20 {
+            storage.set(pos(id), BlockId(id as u16));
+        }
+        for id in 1..18 {
+            storage.set(pos(id), BlockId::AIR);
+        }
+
+        storage.compact();
+
+        let BlockStorage::Paletted { palette, bits, .. } = &storage else {
+            panic!("storage should still hold several block types");
+        };
+        assert_eq!(palette.len(), 3);
+        assert_eq!(*bits, 2);
+
+        assert_eq!(storage.get(pos(18)), BlockId(18));
+        assert_eq!(storage.get(pos(19)), BlockId(19));
+        assert_eq!(storage.get(pos(5)), BlockId::AIR);
+    }
+}
@@ -0,0 +1,202 @@
+use glam::{IVec3, Vec3, ivec3};
+
+/// Gap left between a moving box and the blocks it collides with, so that it never ends up touching or overlapping them
+const EPSILON: f32 = 1e-4;
+
+/// An axis-aligned bounding box
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// The box filled by the block at `pos`. Blocks are centred on their integer coordinates.
+    pub fn block(pos: IVec3) -> Aabb {
+        let centre = pos.as_vec3();
+        Aabb { min: centre - Vec3::splat(0.5), max: centre + Vec3::splat(0.5) }
+    }
+
+    pub fn translate(&self, offset: Vec3) -> Aabb {
+        Aabb { min: self.min + offset, max: self.max + offset }
+    }
+
+    /// The smallest box containing every point, or `None` if there are no points
+    pub fn enclosing(points: impl IntoIterator<Item = Vec3>) -> Option<Aabb> {
+        points.into_iter().fold(None, |aabb, point| match aabb {
+            Some(Aabb { min, max }) => Some(Aabb { min: min.min(point), max: max.max(point) }),
+            None => Some(Aabb { min: point, max: point })
+        })
+    }
+
+    /// Whether the boxes overlap. Boxes that only touch don't count.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.cmplt(other.max).all() && other.min.cmplt(self.max).all()
+    }
+
+    /// The blocks this box overlaps on one axis, ignoring blocks that it only touches. Blocks are centred on their
+    /// integer coordinates.
+    fn block_range(&self, axis: usize) -> std::ops::RangeInclusive<i32> {
+        let first = (self.min[axis] + 0.5 + EPSILON).floor() as i32;
+        let last = (self.max[axis] + 0.5 - EPSILON).floor() as i32;
+
+        first..=last
+    }
+}
+
+/// Moves `aabb` by up to `delta`, stopping it at any block for which `is_solid` returns true. Movement is resolved one
+/// axis at a time (Y, then X, then Z), so a box moving diagonally into a wall slides along it.
+///
+/// Returns the moved box, and the axes on which its movement was blocked.
+pub fn move_and_collide(aabb: Aabb, delta: Vec3, is_solid: impl Fn(IVec3) -> bool) -> (Aabb, [bool; 3]) {
+    let mut aabb = aabb;
+    let mut blocked = [false; 3];
+
+    for axis in [1, 0, 2] {
+        let moved = sweep_axis(&aabb, axis, delta[axis], &is_solid);
+
+        blocked[axis] = moved != delta[axis];
+
+        let mut offset = Vec3::ZERO;
+        offset[axis] = moved;
+        aabb = aabb.translate(offset);
+    }
+
+    (aabb, blocked)
+}
+
+/// How far `aabb` can move along one axis, up to `distance`, before it hits a solid block
+fn sweep_axis(aabb: &Aabb, axis: usize, distance: f32, is_solid: &impl Fn(IVec3) -> bool) -> f32 {
+    if distance == 0.0 {
+        return 0.0;
+    }
+
+    // every block the box passes through on its way
+    let mut swept = *aabb;
+    if distance > 0.0 {
+        swept.max[axis] += distance;
+    } else {
+        swept.min[axis] += distance;
+    }
+
+    let mut allowed = distance;
+
+    for x in swept.block_range(0) {
+        for y in swept.block_range(1) {
+            for z in swept.block_range(2) {
+                let block = ivec3(x, y, z);
+
+                if !is_solid(block) {
+                    continue;
+                }
+
+                let near = block[axis] as f32 - 0.5;
+                let far = block[axis] as f32 + 0.5;
+
+                // only blocks ahead of the box stop it; it can always move out of a block it's already inside
+                if distance > 0.0 && near >= aabb.max[axis] - EPSILON {
+                    allowed = allowed.min(near - aabb.max[axis] - EPSILON).max(0.0);
+                } else if distance < 0.0 && far <= aabb.min[axis] + EPSILON {
+                    allowed = allowed.max(far - aabb.min[axis] + EPSILON).min(0.0);
+                }
+            }
+        }
+    }
+
+    allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use glam::vec3;
+
+    use super::*;
+
+    fn layout(blocks: impl IntoIterator<Item = IVec3>) -> impl Fn(IVec3) -> bool {
+        let blocks: HashSet<IVec3> = blocks.into_iter().collect();
+        move |pos| blocks.contains(&pos)
+    }
+
+    /// A 0.6 x 1.8 x 0.6 box standing with its feet at `feet`
+    fn player_box(feet: Vec3) -> Aabb {
+        Aabb::new(feet - vec3(0.3, 0.0, 0.3), feet + vec3(0.3, 1.8, 0.3))
+    }
+
+    fn floor(y: i32) -> Vec<IVec3> {
+        (-5..=5).flat_map(|x| (-5..=5).map(move |z| ivec3(x, y, z))).collect()
+    }
+
+    #[test]
+    fn lands_on_floor() {
+        // the top of the floor at y = 0 is at 0.5
+        let (aabb, blocked) = move_and_collide(player_box(vec3(0.0, 3.0, 0.0)), vec3(0.0, -5.0, 0.0), layout(floor(0)));
+
+        assert!(blocked[1]);
+        assert!((aabb.min.y - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn does_not_tunnel_through_thin_floor() {
+        let (aabb, blocked) = move_and_collide(player_box(vec3(0.0, 50.0, 0.0)), vec3(0.0, -200.0, 0.0), layout(floor(0)));
+
+        assert!(blocked[1]);
+        assert!(aabb.min.y > 0.49);
+    }
+
+    #[test]
+    fn slides_along_wall() {
+        // a wall at x = 2, spanning the whole floor
+        let wall: Vec<IVec3> = (-5..=5).flat_map(|z| (1..=3).map(move |y| ivec3(2, y, z))).collect();
+        let start = player_box(vec3(0.0, 0.5, 0.0));
+
+        let (aabb, blocked) = move_and_collide(start, vec3(3.0, 0.0, 2.0), layout(wall));
+
+        assert!(blocked[0] && !blocked[2]);
+        assert!((aabb.max.x - 1.5).abs() < 1e-3);
+        assert!((aabb.min.z - start.min.z - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn hits_ceiling() {
+        let (aabb, blocked) = move_and_collide(player_box(vec3(0.0, 0.5, 0.0)), vec3(0.0, 2.0, 0.0), layout(floor(3)));
+
+        assert!(blocked[1]);
+        assert!((aabb.max.y - 2.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn moves_freely_in_open_space() {
+        let start = player_box(vec3(0.0, 0.5, 0.0));
+        let (aabb, blocked) = move_and_collide(start, vec3(1.0, 0.0, -1.0), layout(floor(0)));
+
+        assert_eq!(blocked, [false; 3]);
+        assert_eq!(aabb, start.translate(vec3(1.0, 0.0, -1.0)));
+    }
+
+    #[test]
+    fn intersects_overlapping_blocks_only() {
+        let player = player_box(vec3(0.0, 0.5, 0.0));
+
+        // standing on a block only touches it, but the blocks at the player's feet and head overlap them
+        assert!(!player.intersects(&Aabb::block(ivec3(0, 0, 0))));
+        assert!(player.intersects(&Aabb::block(ivec3(0, 1, 0))));
+        assert!(player.intersects(&Aabb::block(ivec3(0, 2, 0))));
+        assert!(!player.intersects(&Aabb::block(ivec3(1, 1, 0))));
+        assert!(player.translate(vec3(0.5, 0.0, 0.0)).intersects(&Aabb::block(ivec3(1, 1, 0))));
+    }
+
+    #[test]
+    fn can_walk_through_gap() {
+        // two pillars with exactly a block-wide gap between them
+        let pillars = (1..=3).flat_map(|y| [ivec3(-1, y, 3), ivec3(1, y, 3)]);
+        let (_, blocked) = move_and_collide(player_box(vec3(0.0, 0.5, 0.0)), vec3(0.0, 0.0, 5.0), layout(pillars));
+
+        assert!(!blocked[2]);
+    }
+}
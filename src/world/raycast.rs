@@ -0,0 +1,163 @@
+use glam::{IVec3, Vec3};
+
+use super::World;
+
+/// The result of a successful [raycast]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RayHit {
+    /// The block that was hit
+    pub block: IVec3,
+    /// The empty block the ray passed through just before the hit; this is where a block placed on the hit face goes
+    pub previous: IVec3,
+    /// The normal of the face of `block` that the ray entered through
+    pub normal: IVec3,
+    /// The distance along the ray to the point it entered `block`
+    pub distance: f32
+}
+
+/// Walks a ray through the block grid, visiting every block it passes through in order (Amanatides & Woo's DDA), and
+/// returns the first one for which `is_solid` returns true. Blocks are centred on their integer coordinates. The block
+/// containing `origin` is skipped, and the search gives up after `max_distance`.
+pub fn raycast(origin: Vec3, direction: Vec3, max_distance: f32, mut is_solid: impl FnMut(IVec3) -> bool) -> Option<RayHit> {
+    let direction = direction.try_normalize()?;
+
+    // shift into a grid where block `n` covers `n..n + 1`
+    let origin = origin + Vec3::splat(0.5);
+    let mut block = origin.floor().as_ivec3();
+
+    let step = IVec3::select(direction.cmpeq(Vec3::ZERO), IVec3::ZERO, direction.signum().as_ivec3());
+    // how far along the ray we have to move to cross one block on each axis
+    let delta = direction.recip().abs();
+
+    // how far along the ray the next block boundary on each axis is
+    let mut next = Vec3::select(
+        direction.cmpgt(Vec3::ZERO),
+        (block.as_vec3() + Vec3::ONE - origin) * delta,
+        (origin - block.as_vec3()) * delta
+    );
+    next = Vec3::select(direction.cmpeq(Vec3::ZERO), Vec3::splat(f32::INFINITY), next);
+
+    loop {
+        let axis = if next.x <= next.y && next.x <= next.z {
+            0
+        } else if next.y <= next.z {
+            1
+        } else {
+            2
+        };
+
+        let distance = next[axis];
+        if distance > max_distance {
+            return None;
+        }
+
+        let previous = block;
+        block[axis] += step[axis];
+        next[axis] += delta[axis];
+
+        if is_solid(block) {
+            let mut normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+
+            return Some(RayHit { block, previous, normal, distance });
+        }
+    }
+}
+
+impl World {
+    /// Finds the first solid block along a ray, within `max_distance` of its origin. Unloaded chunks are treated as air.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        raycast(origin, direction, max_distance, |pos| self.registry.get(self.get_block(pos)).solid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use glam::{ivec3, vec3};
+
+    use super::*;
+    use crate::world::{BlockRegistry, Chunk, blocks};
+
+    fn layout(blocks: &[IVec3]) -> impl Fn(IVec3) -> bool {
+        let blocks: HashSet<IVec3> = blocks.iter().copied().collect();
+        move |pos| blocks.contains(&pos)
+    }
+
+    #[test]
+    fn hits_block_along_axis() {
+        let hit = raycast(Vec3::ZERO, Vec3::X, 10.0, layout(&[ivec3(5, 0, 0)])).unwrap();
+
+        assert_eq!(hit.block, ivec3(5, 0, 0));
+        assert_eq!(hit.previous, ivec3(4, 0, 0));
+        assert_eq!(hit.normal, ivec3(-1, 0, 0));
+        assert!((hit.distance - 4.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn hits_block_in_negative_direction() {
+        let hit = raycast(vec3(0.2, 0.0, 0.0), Vec3::NEG_Y, 10.0, layout(&[ivec3(0, -3, 0)])).unwrap();
+
+        assert_eq!(hit.block, ivec3(0, -3, 0));
+        assert_eq!(hit.previous, ivec3(0, -2, 0));
+        assert_eq!(hit.normal, ivec3(0, 1, 0));
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn returns_first_block_hit() {
+        let solid = layout(&[ivec3(3, 0, 0), ivec3(2, 0, 0), ivec3(6, 0, 0)]);
+        let hit = raycast(Vec3::ZERO, Vec3::X, 10.0, solid).unwrap();
+
+        assert_eq!(hit.block, ivec3(2, 0, 0));
+    }
+
+    #[test]
+    fn diagonal_ray_enters_through_correct_face() {
+        // travelling mostly along z, the ray reaches the wall at z = 4 before it has moved a whole block along x
+        let wall: Vec<IVec3> = (-5..=5).map(|x| ivec3(x, 0, 4)).collect();
+        let hit = raycast(Vec3::ZERO, vec3(0.3, 0.0, 1.0), 10.0, layout(&wall)).unwrap();
+
+        assert_eq!(hit.block, ivec3(1, 0, 4));
+        assert_eq!(hit.normal, ivec3(0, 0, -1));
+        assert_eq!(hit.previous, ivec3(1, 0, 3));
+    }
+
+    #[test]
+    fn gives_up_after_max_distance() {
+        assert!(raycast(Vec3::ZERO, Vec3::X, 4.0, layout(&[ivec3(5, 0, 0)])).is_none());
+        assert!(raycast(Vec3::ZERO, Vec3::Z, 100.0, layout(&[ivec3(5, 0, 0)])).is_none());
+        assert!(raycast(Vec3::ZERO, Vec3::ZERO, 100.0, |_| true).is_none());
+    }
+
+    #[test]
+    fn ignores_block_containing_origin() {
+        let hit = raycast(Vec3::ZERO, Vec3::X, 10.0, layout(&[ivec3(0, 0, 0), ivec3(1, 0, 0)])).unwrap();
+        assert_eq!(hit.block, ivec3(1, 0, 0));
+    }
+
+    #[test]
+    fn crosses_chunk_borders() {
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+
+        for x in -2..=1 {
+            world.insert_chunk(ivec3(x, 0, 0), Chunk::new());
+        }
+
+        world.set_block(ivec3(16, 2, 0), blocks::STONE);
+        world.set_block(ivec3(-17, 2, 0), blocks::STONE);
+        // water isn't solid, so the ray passes through it
+        world.set_block(ivec3(20, 2, 0), blocks::WATER);
+        world.set_block(ivec3(21, 2, 0), blocks::DIRT);
+
+        let hit = world.raycast(vec3(12.0, 2.0, 0.0), Vec3::X, 20.0).unwrap();
+        assert_eq!((hit.block, hit.previous), (ivec3(16, 2, 0), ivec3(15, 2, 0)));
+
+        let hit = world.raycast(vec3(-1.0, 2.0, 0.0), Vec3::NEG_X, 20.0).unwrap();
+        assert_eq!((hit.block, hit.normal), (ivec3(-17, 2, 0), ivec3(1, 0, 0)));
+
+        let hit = world.raycast(vec3(18.0, 2.0, 0.0), Vec3::X, 20.0).unwrap();
+        assert_eq!(hit.block, ivec3(21, 2, 0));
+    }
+}
@@ -0,0 +1,409 @@
+//! Stores chunks on disk, grouped into region files of `REGION_SIZE`³ chunks each.
+//!
+//! A region file starts with a header, followed by the compressed data of each chunk it contains. All integers are
+//! little endian.
+//!
+//! | bytes | contents                                                                          |
+//! |-------|-----------------------------------------------------------------------------------|
+//! | 4     | magic number, `VXRG`                                                              |
+//! | 2     | format version                                                                    |
+//! | 2     | region size, in chunks along each axis                                            |
+//! | 8 × n | index of every chunk in the region: a `u32` file offset and a `u32` length        |
+//! | ...   | chunk data, each compressed with zlib; chunks that aren't stored have length zero |
+//!
+//! Uncompressed, a chunk is the `u16` ID of each of its blocks, in x, y, z order.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use glam::{IVec3, ivec3};
+
+use super::{Chunk, BlockId, CHUNK_SIZE};
+
+/// The number of chunks along each axis of a region
+pub const REGION_SIZE: i32 = 8;
+
+const MAGIC: [u8; 4] = *b"VXRG";
+const VERSION: u16 = 1;
+
+const CHUNKS_PER_REGION: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+const HEADER_SIZE: usize = 8 + CHUNKS_PER_REGION * 8;
+const CHUNK_BYTES: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize * 2;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RegionError {
+    #[error("region file I/O failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a region file")]
+    BadMagic,
+    #[error("unsupported region format version {0}")]
+    UnsupportedVersion(u16),
+    #[error("region file holds {0}³ chunks, expected {REGION_SIZE}³")]
+    WrongRegionSize(u16),
+    #[error("region file is truncated")]
+    Truncated,
+    #[error("data for chunk {0} is corrupt")]
+    CorruptChunk(IVec3)
+}
+
+/// A chunk's index within its region, and its compressed data
+type EncodedChunk = (usize, Vec<u8>);
+
+/// Reads and writes chunks to a directory of region files
+pub struct RegionStore {
+    dir: PathBuf
+}
+
+impl RegionStore {
+    /// Uses the region files in `dir`. The directory is created when the first chunk is saved.
+    pub fn new(dir: impl Into<PathBuf>) -> RegionStore {
+        RegionStore { dir: dir.into() }
+    }
+
+    /// Splits a chunk coordinate into the coordinate of its region, and its index within that region
+    fn locate(chunk: IVec3) -> (IVec3, usize) {
+        let region = ivec3(chunk.x.div_euclid(REGION_SIZE), chunk.y.div_euclid(REGION_SIZE), chunk.z.div_euclid(REGION_SIZE));
+        let local = chunk - region * REGION_SIZE;
+
+        (region, (local.x * REGION_SIZE * REGION_SIZE + local.y * REGION_SIZE + local.z) as usize)
+    }
+
+    fn region_path(&self, region: IVec3) -> PathBuf {
+        self.dir.join(format!("r.{}.{}.{}.region", region.x, region.y, region.z))
+    }
+
+    /// Loads a chunk, returning `None` if it has never been saved
+    pub fn load_chunk(&self, pos: IVec3) -> Result<Option<Chunk>, RegionError> {
+        let (region, index) = RegionStore::locate(pos);
+
+        let mut file = match fs::File::open(self.region_path(region)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into())
+        };
+
+        let file_len = file.metadata()?.len();
+        let header = read_header(&mut file)?;
+        let (offset, len) = header[index];
+
+        if len == 0 {
+            return Ok(None);
+        }
+
+        if offset as u64 + len as u64 > file_len {
+            return Err(RegionError::Truncated);
+        }
+
+        let mut compressed = vec![0; len as usize];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut compressed)?;
+
+        decode_chunk(&compressed)
+            .map(Some)
+            .ok_or(RegionError::CorruptChunk(pos))
+    }
+
+    /// Saves a set of chunks, rewriting each region file they belong to once
+    pub fn save_chunks<'a>(&self, chunks: impl IntoIterator<Item = (IVec3, &'a Chunk)>) -> Result<(), RegionError> {
+        let mut regions: HashMap<IVec3, Vec<EncodedChunk>> = HashMap::new();
+
+        for (pos, chunk) in chunks {
+            let (region, index) = RegionStore::locate(pos);
+            regions.entry(region).or_default().push((index, encode_chunk(chunk)?));
+        }
+
+        for (region, chunks) in regions {
+            self.update_region(region, chunks)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces some of the chunks in a region file, keeping the rest
+    fn update_region(&self, region: IVec3, updated: Vec<EncodedChunk>) -> Result<(), RegionError> {
+        let path = self.region_path(region);
+
+        let mut chunks: Vec<Option<Vec<u8>>> = match fs::read(&path) {
+            Ok(bytes) => match read_all_chunks(&bytes) {
+                Ok(chunks) => chunks,
+                // failing here would stop anything in this region being saved ever again, so salvage what's left
+                Err(e) => {
+                    let backup = path.with_extension("region.corrupt");
+                    log::warn!("region file {} is unreadable ({}); backing it up to {} and rewriting it", path.display(), e, backup.display());
+
+                    fs::write(&backup, &bytes)?;
+                    salvage_chunks(&bytes)
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![None; CHUNKS_PER_REGION],
+            Err(e) => return Err(e.into())
+        };
+
+        for (index, data) in updated {
+            chunks[index] = Some(data);
+        }
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        let mut body = vec![];
+
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&(REGION_SIZE as u16).to_le_bytes());
+
+        for chunk in &chunks {
+            let (offset, len) = match chunk {
+                Some(data) => ((HEADER_SIZE + body.len()) as u32, data.len() as u32),
+                None => (0, 0)
+            };
+
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(&len.to_le_bytes());
+
+            if let Some(data) = chunk {
+                body.extend_from_slice(data);
+            }
+        }
+
+        fs::create_dir_all(&self.dir)?;
+
+        // write to a temporary file first, so that a crash part way through doesn't destroy the region
+        let temp = path.with_extension("region.tmp");
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(&header)?;
+        file.write_all(&body)?;
+        file.sync_all()?;
+
+        fs::rename(&temp, &path)?;
+
+        Ok(())
+    }
+}
+
+/// Reads and validates a region header, returning the offset and length of each chunk
+fn read_header(reader: &mut impl Read) -> Result<Vec<(u32, u32)>, RegionError> {
+    let mut header = vec![0; HEADER_SIZE];
+
+    reader.read_exact(&mut header).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => RegionError::Truncated,
+        _ => e.into()
+    })?;
+
+    if header[0..4] != MAGIC {
+        return Err(RegionError::BadMagic);
+    }
+
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(RegionError::UnsupportedVersion(version));
+    }
+
+    let size = u16::from_le_bytes([header[6], header[7]]);
+    if size as i32 != REGION_SIZE {
+        return Err(RegionError::WrongRegionSize(size));
+    }
+
+    let index = header[8..].chunks_exact(8)
+        .map(|entry| (
+            u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+            u32::from_le_bytes(entry[4..8].try_into().unwrap())
+        ))
+        .collect();
+
+    Ok(index)
+}
+
+/// Reads the compressed data of every chunk in a region file
+fn read_all_chunks(bytes: &[u8]) -> Result<Vec<Option<Vec<u8>>>, RegionError> {
+    let header = read_header(&mut &bytes[..])?;
+
+    header.into_iter()
+        .map(|(offset, len)| {
+            if len == 0 {
+                return Ok(None);
+            }
+
+            bytes.get(offset as usize..offset as usize + len as usize)
+                .map(|data| Some(data.to_vec()))
+                .ok_or(RegionError::Truncated)
+        })
+        .collect()
+}
+
+/// Reads whatever chunks can still be decoded from a damaged region file, treating the rest as if they were never saved
+fn salvage_chunks(bytes: &[u8]) -> Vec<Option<Vec<u8>>> {
+    let Ok(header) = read_header(&mut &bytes[..]) else {
+        return vec![None; CHUNKS_PER_REGION];
+    };
+
+    header.into_iter()
+        .map(|(offset, len)| {
+            let data = bytes.get(offset as usize..offset as usize + len as usize)?;
+            (len > 0 && decode_chunk(data).is_some()).then(|| data.to_vec())
+        })
+        .collect()
+}
+
+fn encode_chunk(chunk: &Chunk) -> Result<Vec<u8>, RegionError> {
+    let mut raw = Vec::with_capacity(CHUNK_BYTES);
+
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                raw.extend_from_slice(&chunk.get(ivec3(x, y, z)).0.to_le_bytes());
+            }
+        }
+    }
+
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(&raw)?;
+
+    Ok(encoder.finish()?)
+}
+
+/// Decompresses a chunk, returning `None` if the data is invalid
+fn decode_chunk(compressed: &[u8]) -> Option<Chunk> {
+    let mut raw = Vec::with_capacity(CHUNK_BYTES);
+
+    // read one byte more than expected, to catch chunks that are too long
+    ZlibDecoder::new(compressed)
+        .take(CHUNK_BYTES as u64 + 1)
+        .read_to_end(&mut raw)
+        .ok()?;
+
+    if raw.len() != CHUNK_BYTES {
+        return None;
+    }
+
+    let mut chunk = Chunk::new();
+    let mut ids = raw.chunks_exact(2).map(|id| BlockId(u16::from_le_bytes([id[0], id[1]])));
+
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.set(ivec3(x, y, z), ids.next()?);
+            }
+        }
+    }
+
+    chunk.compact();
+    Some(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A region store in a fresh temporary directory
+    fn temp_store(name: &str) -> RegionStore {
+        let dir = std::env::temp_dir().join(format!("engine-region-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        RegionStore::new(dir)
+    }
+
+    fn test_chunk(id: BlockId) -> Chunk {
+        let mut chunk = Chunk::new();
+
+        for i in 0..CHUNK_SIZE {
+            chunk.set(ivec3(i, i, 15 - i), id);
+        }
+
+        chunk
+    }
+
+    #[test]
+    fn round_trip() {
+        let store = temp_store("round-trip");
+
+        // the second chunk lies in a different region to the first
+        let chunks = [(ivec3(0, 0, 0), test_chunk(BlockId(3))), (ivec3(-1, 2, 9), test_chunk(BlockId(7)))];
+        store.save_chunks(chunks.iter().map(|(pos, chunk)| (*pos, chunk))).unwrap();
+
+        // saving again keeps the chunks already in the region
+        store.save_chunks([(ivec3(1, 0, 0), &test_chunk(BlockId(1)))]).unwrap();
+
+        for (pos, chunk) in &chunks {
+            let loaded = store.load_chunk(*pos).unwrap().expect("chunk was not saved");
+            assert_eq!(loaded.get(ivec3(4, 4, 11)), chunk.get(ivec3(4, 4, 11)));
+            assert_eq!(loaded.get(ivec3(4, 5, 11)), BlockId::AIR);
+        }
+
+        assert!(store.load_chunk(ivec3(2, 0, 0)).unwrap().is_none());
+        assert!(store.load_chunk(ivec3(100, 0, 0)).unwrap().is_none());
+
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_region_is_an_error() {
+        let store = temp_store("truncated");
+        let pos = ivec3(0, 0, 0);
+
+        store.save_chunks([(pos, &test_chunk(BlockId(2)))]).unwrap();
+
+        let path = store.region_path(ivec3(0, 0, 0));
+        let bytes = fs::read(&path).unwrap();
+
+        fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+        assert!(matches!(store.load_chunk(pos), Err(RegionError::Truncated)));
+
+        fs::write(&path, &bytes[..100]).unwrap();
+        assert!(matches!(store.load_chunk(pos), Err(RegionError::Truncated)));
+
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_region_is_an_error() {
+        let store = temp_store("corrupt");
+        let pos = ivec3(0, 0, 0);
+
+        store.save_chunks([(pos, &test_chunk(BlockId(2)))]).unwrap();
+
+        let path = store.region_path(ivec3(0, 0, 0));
+        let mut bytes = fs::read(&path).unwrap();
+
+        let last = bytes.len() - 1;
+        bytes[HEADER_SIZE..last].fill(0xff);
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(store.load_chunk(pos), Err(RegionError::CorruptChunk(_))));
+
+        bytes[0] = b'X';
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(store.load_chunk(pos), Err(RegionError::BadMagic)));
+
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn saving_recovers_damaged_regions() {
+        let store = temp_store("recover");
+        let (first, second) = (ivec3(0, 0, 0), ivec3(0, 0, 1));
+
+        store.save_chunks([(first, &test_chunk(BlockId(2))), (second, &test_chunk(BlockId(3)))]).unwrap();
+
+        // cut off the end of the second chunk, which is stored last
+        let path = store.region_path(ivec3(0, 0, 0));
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+
+        store.save_chunks([(ivec3(1, 0, 0), &test_chunk(BlockId(4)))]).unwrap();
+
+        assert!(store.load_chunk(first).unwrap().is_some());
+        assert!(store.load_chunk(second).unwrap().is_none());
+        assert!(store.load_chunk(ivec3(1, 0, 0)).unwrap().is_some());
+        assert_eq!(fs::read(path.with_extension("region.corrupt")).unwrap(), &bytes[..bytes.len() - 10]);
+
+        // nothing can be salvaged without a valid header, but the region can still be saved to
+        fs::write(&path, b"not a region").unwrap();
+        store.save_chunks([(second, &test_chunk(BlockId(5)))]).unwrap();
+
+        assert!(store.load_chunk(first).unwrap().is_none());
+        assert_eq!(store.load_chunk(second).unwrap().unwrap().get(ivec3(0, 0, 15)), BlockId(5));
+
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+}
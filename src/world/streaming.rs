@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+
+use glam::{IVec3, Vec3, ivec3};
+
+use super::{World, Chunk, TerrainGenerator, RegionStore};
+
+/// Loads the chunks around a point of interest (usually the camera) as it moves through the world, and unloads the
+/// chunks it leaves behind. Chunks that have been saved are loaded from disk; the rest are generated. Edited chunks
+/// are saved when they are unloaded.
+pub struct ChunkManager {
+    generator: Box<dyn TerrainGenerator>,
+    store: RegionStore,
+    /// Chunks within this many chunks of the centre are loaded
+    pub view_radius: i32,
+    /// Loaded chunks further than this many chunks from the centre are unloaded. This is larger than the view radius,
+    /// so that moving back and forth across a chunk border doesn't repeatedly load and unload the same chunks.
+    pub unload_radius: i32,
+    /// The maximum number of chunks generated each frame
+    pub generation_budget: usize,
+    /// The chunk the centre was in during the last update
+    centre: Option<IVec3>,
+    /// Chunks waiting to be generated, nearest first
+    queue: VecDeque<IVec3>
+}
+
+impl ChunkManager {
+    pub fn new(generator: Box<dyn TerrainGenerator>, store: RegionStore, view_radius: i32, unload_radius: i32) -> ChunkManager {
+        ChunkManager {
+            generator,
+            store,
+            view_radius,
+            unload_radius: unload_radius.max(view_radius),
+            generation_budget: 4,
+            centre: None,
+            queue: VecDeque::new()
+        }
+    }
+
+    /// Loads and unloads chunks around `position`, loading or generating at most
+    /// [generation_budget](Self::generation_budget) new chunks
+    pub fn update(&mut self, world: &mut World, position: Vec3) {
+        let (centre, _) = World::split_pos(World::block_at_point(position));
+
+        if self.centre != Some(centre) {
+            self.centre = Some(centre);
+            self.unload_distant(world, centre);
+            self.queue_missing(world, centre);
+        }
+
+        let mut generated = 0;
+
+        while generated < self.generation_budget {
+            let Some(pos) = self.queue.pop_front() else {
+                break;
+            };
+
+            if world.chunk(pos).is_none() {
+                world.insert_chunk(pos, self.load_or_generate(pos));
+                generated += 1;
+            }
+        }
+    }
+
+    /// Whether there are chunks within the view radius still waiting to be loaded
+    pub fn is_loading(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    fn load_or_generate(&self, pos: IVec3) -> Chunk {
+        match self.store.load_chunk(pos) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => self.generator.generate(pos),
+            Err(e) => {
+                log::error!("failed to load chunk at {}, regenerating it: {}", pos, e);
+                self.generator.generate(pos)
+            }
+        }
+    }
+
+    /// Unloads the chunks outside the unload radius, saving those that have been edited. If saving fails, the edited
+    /// chunks stay loaded so that their edits aren't lost, and are tried again next time.
+    fn unload_distant(&mut self, world: &mut World, centre: IVec3) {
+        let distant: Vec<IVec3> = world.chunk_positions()
+            .filter(|&pos| (pos - centre).length_squared() > self.unload_radius * self.unload_radius)
+            .collect();
+
+        let modified: Vec<IVec3> = distant.iter()
+            .copied()
+            .filter(|&pos| world.chunk(pos).is_some_and(Chunk::is_modified))
+            .collect();
+
+        let saved = self.store.save_chunks(modified.iter().filter_map(|&pos| world.chunk(pos).map(|chunk| (pos, chunk))));
+
+        if let Err(e) = &saved {
+            log::error!("failed to save unloaded chunks, keeping {} edited chunks loaded: {}", modified.len(), e);
+        }
+
+        for pos in distant {
+            if saved.is_ok() || !modified.contains(&pos) {
+                world.remove_chunk(pos);
+            }
+        }
+    }
+
+    /// Saves every loaded chunk that has been edited since it was last saved. If saving fails, the chunks stay marked
+    /// as edited.
+    pub fn save_all(&self, world: &mut World) {
+        let count = world.modified_chunks().count();
+
+        if count == 0 {
+            return;
+        }
+
+        log::info!("saving {} modified chunks", count);
+
+        match self.store.save_chunks(world.modified_chunks()) {
+            Ok(()) => world.mark_saved(),
+            Err(e) => log::error!("failed to save chunks: {}", e)
+        }
+    }
+
+    /// Rebuilds the queue of chunks to generate from the unloaded chunks within the view radius, closest first
+    fn queue_missing(&mut self, world: &World, centre: IVec3) {
+        let radius = self.view_radius;
+        let mut missing = vec![];
+
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                for z in -radius..=radius {
+                    let offset = ivec3(x, y, z);
+
+                    if offset.length_squared() <= radius * radius && world.chunk(centre + offset).is_none() {
+                        missing.push(centre + offset);
+                    }
+                }
+            }
+        }
+
+        missing.sort_by_key(|&pos| (pos - centre).length_squared());
+        self.queue = missing.into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{BlockRegistry, MeshingMode, blocks};
+
+    /// Generates nothing but air
+    struct Empty;
+
+    impl TerrainGenerator for Empty {
+        fn generate(&self, _: IVec3) -> Chunk {
+            Chunk::new()
+        }
+    }
+
+    #[test]
+    fn edits_survive_failed_saves() {
+        let dir = std::env::temp_dir().join(format!("engine-streaming-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // a file where the region directory should be, so that saving fails
+        let blocked = dir.join("blocked");
+        std::fs::write(&blocked, b"").unwrap();
+
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+        let mut chunks = ChunkManager::new(Box::new(Empty), RegionStore::new(&blocked), 1, 1);
+
+        chunks.update(&mut world, Vec3::ZERO);
+        while chunks.is_loading() {
+            chunks.update(&mut world, Vec3::ZERO);
+        }
+
+        let edited = ivec3(1, 0, 0);
+        world.set_block(World::chunk_origin(edited), blocks::STONE);
+
+        let far_away = Vec3::splat(1000.0);
+        chunks.update(&mut world, far_away);
+        assert!(world.chunk(edited).is_some_and(Chunk::is_modified));
+        assert!(world.chunk(IVec3::ZERO).is_none());
+
+        // once saving works again, the edited chunk is saved and unloaded the next time the centre moves
+        chunks.store = RegionStore::new(dir.join("world"));
+        chunks.update(&mut world, Vec3::splat(2000.0));
+        assert!(world.chunk(edited).is_none());
+        assert_eq!(chunks.store.load_chunk(edited).unwrap().unwrap().get(IVec3::ZERO), blocks::STONE);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn streamed_chunks_use_the_world_meshing_mode() {
+        let dir = std::env::temp_dir().join(format!("engine-streaming-meshing-{}", std::process::id()));
+
+        let mut world = World::new(BlockRegistry::with_default_blocks());
+        let mut chunks = ChunkManager::new(Box::new(Empty), RegionStore::new(&dir), 1, 1);
+
+        chunks.update(&mut world, Vec3::ZERO);
+        world.set_meshing(MeshingMode::Naive);
+
+        // chunks loaded after switching modes, both in the old area and somewhere new
+        for centre in [Vec3::ZERO, Vec3::splat(100.0)] {
+            chunks.update(&mut world, centre);
+            while chunks.is_loading() {
+                chunks.update(&mut world, centre);
+            }
+        }
+
+        assert!(world.chunk_positions().all(|pos| world.chunk(pos).unwrap().meshing() == MeshingMode::Naive));
+    }
+}
@@ -0,0 +1,232 @@
+use glam::{IVec3, ivec3};
+
+use super::{Chunk, BlockId, World, blocks, CHUNK_SIZE};
+
+/// Fills in chunks of the world as they are needed. Implementations must be deterministic: generating the same chunk
+/// coordinate twice has to give the same blocks, so that chunks can be generated on demand in any order.
+pub trait TerrainGenerator: Send + Sync {
+    fn generate(&self, pos: IVec3) -> Chunk;
+}
+
+/// Hashes a lattice point and a seed into 64 pseudo-random bits
+fn hash(seed: u64, x: i64, z: i64) -> u64 {
+    let mut h = seed
+        ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (z as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+
+    // splitmix64 finaliser
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+/// 2D gradient noise, roughly in the range -1 to 1. Only uses basic arithmetic, so results are the same on every
+/// platform.
+fn gradient_noise(seed: u64, x: f64, z: f64) -> f64 {
+    const DIAGONAL: f64 = std::f64::consts::FRAC_1_SQRT_2;
+    const GRADIENTS: [(f64, f64); 8] = [
+        (1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0),
+        (DIAGONAL, DIAGONAL), (-DIAGONAL, DIAGONAL), (DIAGONAL, -DIAGONAL), (-DIAGONAL, -DIAGONAL)
+    ];
+
+    let (x0, z0) = (x.floor(), z.floor());
+    let (dx, dz) = (x - x0, z - z0);
+    let (ix, iz) = (x0 as i64, z0 as i64);
+
+    let corner = |cx: i64, cz: i64| {
+        let (gx, gz) = GRADIENTS[(hash(seed, ix + cx, iz + cz) & 7) as usize];
+        gx * (dx - cx as f64) + gz * (dz - cz as f64)
+    };
+
+    let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+
+    let (u, v) = (fade(dx), fade(dz));
+
+    lerp(
+        lerp(corner(0, 0), corner(1, 0), u),
+        lerp(corner(0, 1), corner(1, 1), u),
+        v
+    )
+}
+
+/// Terrain shaped by a heightmap of layered gradient noise: stone, covered by a few layers of dirt and a layer of grass,
+/// with sand along the shore and water filling everything below sea level.
+pub struct NoiseTerrain {
+    seed: u64,
+    /// Empty space at or below this height is filled with water
+    pub sea_level: i32,
+    /// The average height of the terrain
+    pub base_height: f64,
+    /// How far the terrain can rise above or fall below its base height
+    pub amplitude: f64,
+    /// The width, in blocks, of the largest features in the heightmap
+    pub scale: f64,
+    /// The number of layers of noise summed to make the heightmap; each adds finer detail
+    pub octaves: u32,
+    /// How many blocks of dirt lie between the surface and the stone below
+    pub dirt_depth: i32
+}
+
+impl NoiseTerrain {
+    pub fn new(seed: u64) -> NoiseTerrain {
+        NoiseTerrain {
+            seed,
+            sea_level: 0,
+            base_height: 4.0,
+            amplitude: 32.0,
+            scale: 96.0,
+            octaves: 5,
+            dirt_depth: 3
+        }
+    }
+
+    /// The height of the highest solid block in the column at `(x, z)`
+    pub fn height_at(&self, x: i32, z: i32) -> i32 {
+        let (mut amplitude, mut frequency) = (1.0, 1.0 / self.scale);
+        let (mut total, mut max) = (0.0, 0.0);
+
+        for octave in 0..self.octaves {
+            total += amplitude * gradient_noise(self.seed.wrapping_add(octave as u64), x as f64 * frequency, z as f64 * frequency);
+            max += amplitude;
+
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+
+        (self.base_height + total / max * self.amplitude).floor() as i32
+    }
+
+    fn block_at(&self, y: i32, height: i32) -> BlockId {
+        let shore = height <= self.sea_level + 1;
+
+        if y > height {
+            if y <= self.sea_level { blocks::WATER } else { BlockId::AIR }
+        } else if y == height {
+            if shore { blocks::SAND } else { blocks::GRASS }
+        } else if y > height - self.dirt_depth {
+            if shore { blocks::SAND } else { blocks::DIRT }
+        } else {
+            blocks::STONE
+        }
+    }
+}
+
+impl TerrainGenerator for NoiseTerrain {
+    fn generate(&self, pos: IVec3) -> Chunk {
+        let origin = World::chunk_origin(pos);
+        let mut chunk = Chunk::new();
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let height = self.height_at(origin.x + x, origin.z + z);
+
+                for y in 0..CHUNK_SIZE {
+                    let id = self.block_at(origin.y + y, height);
+
+                    if !id.is_air() {
+                        chunk.set(ivec3(x, y, z), id);
+                    }
+                }
+            }
+        }
+
+        chunk.compact();
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks_of(chunk: &Chunk) -> Vec<u16> {
+        let mut ids = vec![];
+
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    ids.push(chunk.get(ivec3(x, y, z)).0);
+                }
+            }
+        }
+
+        ids
+    }
+
+    /// FNV-1a over the chunk's block IDs
+    fn checksum(chunk: &Chunk) -> u64 {
+        blocks_of(chunk).iter().fold(0xcbf2_9ce4_8422_2325, |hash, &id| {
+            (hash ^ id as u64).wrapping_mul(0x100_0000_01b3)
+        })
+    }
+
+    #[test]
+    fn same_seed_gives_same_chunk() {
+        let a = NoiseTerrain::new(1234);
+        let b = NoiseTerrain::new(1234);
+
+        for pos in [ivec3(0, 0, 0), ivec3(-3, -1, 7), ivec3(100, 1, -100)] {
+            assert_eq!(blocks_of(&a.generate(pos)), blocks_of(&b.generate(pos)));
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_terrain() {
+        let a = NoiseTerrain::new(1);
+        let b = NoiseTerrain::new(2);
+
+        let heights = |terrain: &NoiseTerrain| (0..64).map(|i| terrain.height_at(i * 7, i * 13)).collect::<Vec<_>>();
+        assert_ne!(heights(&a), heights(&b));
+    }
+
+    #[test]
+    fn columns_are_layered() {
+        let terrain = NoiseTerrain::new(42);
+
+        for x in -32..32 {
+            for z in -32..32 {
+                let height = terrain.height_at(x, z);
+
+                assert_eq!(terrain.block_at(height - terrain.dirt_depth, height), blocks::STONE);
+                assert!(terrain.block_at(height + 1, height) == BlockId::AIR || height < terrain.sea_level);
+
+                if height > terrain.sea_level + 1 {
+                    assert_eq!(terrain.block_at(height, height), blocks::GRASS);
+                    assert_eq!(terrain.block_at(height - 1, height), blocks::DIRT);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn golden_heights() {
+        let terrain = NoiseTerrain::new(42);
+
+        let heights: Vec<i32> = [(37, -91), (-200, 64), (1000, 1000), (12345, -6789)].iter()
+            .map(|&(x, z)| terrain.height_at(x, z))
+            .collect();
+
+        assert_eq!(heights, GOLDEN_HEIGHTS);
+    }
+
+    #[test]
+    fn golden_chunks() {
+        let terrain = NoiseTerrain::new(42);
+
+        for (pos, expected) in GOLDEN_CHUNKS {
+            assert_eq!(checksum(&terrain.generate(pos)), expected, "chunk at {}", pos);
+        }
+    }
+
+    // recorded from the generator; these only need updating when the terrain is deliberately changed
+    const GOLDEN_HEIGHTS: [i32; 4] = [6, -1, -2, 5];
+
+    const GOLDEN_CHUNKS: [(IVec3, u64); 3] = [
+        (IVec3::new(0, 0, 0), 15202413640584029540),
+        (IVec3::new(2, 0, -1), 11509083178360065613),
+        (IVec3::new(-5, 0, 3), 9703289124791290929)
+    ];
+}